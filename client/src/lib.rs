@@ -17,28 +17,439 @@
  * and navigate to version 3 of the GNU Affero General Public License.
  */
 
-use hyper::Client;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+use hyper::{body, header, Body, Method, Request, StatusCode};
+use eyre::Result;
+use slint::{Timer, TimerMode};
+use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use thebestofcmu_common::{normalize_phone_number, ClientRSVP, PostPath, RSVP_SCHEMA, RsvpDetails, ServerResponse};
 
 slint::slint! {
+    import { Button, CheckBox, LineEdit, SpinBox, VerticalBox } from "std-widgets.slint";
+
     Survey := Window {
-        Text {
-            text: "Hello world";
-            color: green;
+        title: "RSVP";
+        // One of the Screen values in lib.rs
+        property <int> screen: 0;
+        property <string> first_name;
+        property <string> phone_number;
+        property <string> email_address;
+        property <bool> attending: true;
+        property <int> guest_count: 1;
+        // Whether the last RSVP failed for reasons which sending it again may fix
+        property <bool> can_retry: false;
+        // Problems with the form, or the server's answer
+        property <string> status;
+        // What was recorded, once the server accepts an RSVP
+        property <string> summary;
+        // When the invitee RSVPed before
+        property <string> rsvped_on;
+        // Which dot of the spinner is lit
+        property <int> spinner_frame: 0;
+        callback submit();
+
+        if root.screen == 0 : VerticalBox {
+            Text { text: "First name"; }
+            LineEdit {
+                placeholder-text: "First name";
+                text <=> root.first_name;
+            }
+            Text {
+                text: "A phone number or email address, so the coordinator can reach you";
+                wrap: word-wrap;
+            }
+            Text { text: "Phone number"; }
+            LineEdit {
+                placeholder-text: "412 555 0123, or +44 20 7946 0018";
+                text <=> root.phone_number;
+            }
+            Text { text: "Email address"; }
+            LineEdit {
+                placeholder-text: "you@example.com";
+                text <=> root.email_address;
+            }
+            CheckBox {
+                text: "I'm coming";
+                checked <=> root.attending;
+            }
+            Text {
+                text: "Party size, including yourself";
+                visible: root.attending;
+            }
+            SpinBox {
+                minimum: 1;
+                maximum: 4;
+                value <=> root.guest_count;
+                visible: root.attending;
+            }
+            Button {
+                text: root.can-retry ? "Try again" : "RSVP";
+                clicked => { root.submit(); }
+            }
+            Text {
+                text: root.status;
+                wrap: word-wrap;
+            }
+        }
+        if root.screen == 1 : VerticalBox {
+            alignment: center;
+            Text {
+                text: "Sending your RSVP...";
+                horizontal-alignment: center;
+            }
+            HorizontalLayout {
+                alignment: center;
+                spacing: 8px;
+                for dot in [0, 1, 2] : Rectangle {
+                    width: 12px;
+                    height: 12px;
+                    border-radius: 6px;
+                    background: #3a7bd5;
+                    opacity: root.spinner-frame == dot ? 1 : 0.3;
+                }
+            }
+        }
+        if root.screen == 2 : VerticalBox {
+            Text {
+                text: root.status;
+                font-size: 20px;
+                wrap: word-wrap;
+            }
+            Text {
+                text: root.summary;
+                wrap: word-wrap;
+            }
+            Button {
+                text: "Change my answers";
+                clicked => {
+                    root.status = "";
+                    root.screen = 0;
+                }
+            }
+        }
+        if root.screen == 3 : VerticalBox {
+            Text {
+                text: "You already RSVPed on " + root.rsvped-on + ".";
+                wrap: word-wrap;
+            }
+            Button {
+                text: "Back";
+                clicked => {
+                    root.status = "";
+                    root.screen = 0;
+                }
+            }
+        }
+        if root.screen == 4 : VerticalBox {
+            Text {
+                text: "You are not on the guest list. Please check the spelling of your name, or ask the coordinator who linked you to this website.";
+                wrap: word-wrap;
+            }
+            Button {
+                text: "Try another name";
+                clicked => {
+                    root.status = "";
+                    root.screen = 0;
+                }
+            }
+        }
+    }
+}
+
+/// Which screen the survey shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Screen {
+    Form = 0,
+    Submitting = 1,
+    Recorded = 2,
+    AlreadyRSVPed = 3,
+    NotInvited = 4
+}
+
+impl Screen {
+    // Answers which leave the invitee something to fix are shown on the form
+    fn after(response: &ServerResponse) -> Self {
+        match response {
+            ServerResponse::Success | ServerResponse::Queued => Screen::Recorded,
+            ServerResponse::AlreadyRSVPed(_) => Screen::AlreadyRSVPed,
+            ServerResponse::NotInvited => Screen::NotInvited,
+            ServerResponse::InviteExpired | ServerResponse::InviteIdRequired
+            | ServerResponse::NotYetRSVPed => Screen::Form
         }
     }
 }
 
 #[wasm_bindgen(start)]
 pub fn main() -> Result<(), JsValue> {
-    let tls_connector = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .enable_http2()
-        .build();
-    let client: Client<_, hyper::Body> = Client::builder()
-        .build(tls_connector);
-    Survey::new().run();
+    let window = web_sys::window()
+        .ok_or_else(|| JsValue::from_str("No window"))?;
+    let origin = window.location().origin()?;
+
+    let survey = Survey::new();
+    let survey_handle = survey.as_weak();
+    survey.on_submit(move || {
+        let survey = match survey_handle.upgrade() {
+            Some(survey) => survey,
+            None => return
+        };
+        let rsvp = match rsvp_from_form(&survey) {
+            Ok(rsvp) => rsvp,
+            Err(problems) => {
+                survey.set_status(problems.into());
+                return;
+            }
+        };
+        survey.set_can_retry(false);
+        survey.set_status("".into());
+        survey.set_screen(Screen::Submitting as i32);
+
+        let (window, origin, survey_handle) = (window.clone(), origin.clone(), survey_handle.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let response = send_rsvp(&window, &origin, rsvp.clone()).await;
+            let survey = match survey_handle.upgrade() {
+                Some(survey) => survey,
+                None => return
+            };
+            match response {
+                Ok(response) => show_response(&survey, &rsvp, &response),
+                Err(e) => {
+                    survey.set_can_retry(e.worth_retrying());
+                    survey.set_status(e.to_string().into());
+                    survey.set_screen(Screen::Form as i32);
+                }
+            }
+        });
+    });
+
+    let spinner = Timer::default();
+    let survey_handle = survey.as_weak();
+    spinner.start(TimerMode::Repeated, Duration::from_millis(300), move || {
+        if let Some(survey) = survey_handle.upgrade() {
+            if survey.get_screen() == Screen::Submitting as i32 {
+                survey.set_spinner_frame((survey.get_spinner_frame() + 1) % 3);
+            }
+        }
+    });
+    survey.run();
     Ok(())
 }
+
+fn show_response(survey: &Survey, rsvp: &ClientRSVP, response: &ServerResponse) {
+    if let ServerResponse::Success | ServerResponse::Queued = response {
+        survey.set_summary(format!("{}\n{}", rsvp.first_name, rsvp.details).into());
+    }
+    if let Some(rsvped_at) = response.previous_rsvp_time() {
+        survey.set_rsvped_on(format_date(rsvped_at).into());
+    }
+    survey.set_status(describe_response(response).into());
+    survey.set_screen(Screen::after(response) as i32);
+}
+
+// In the browser's own language and time zone
+fn format_date(time: SystemTime) -> String {
+    let millis_since_epoch = time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis())
+        .unwrap_or(0);
+    let date = js_sys::Date::new(&JsValue::from_f64(millis_since_epoch as f64));
+    String::from(date.to_locale_date_string("default", &JsValue::UNDEFINED))
+}
+
+// Yields a message listing every problem if the form is not filled in properly
+fn rsvp_from_form(survey: &Survey) -> std::result::Result<ClientRSVP, String> {
+    rsvp_from_fields(&survey.get_first_name(), &survey.get_phone_number(), &survey.get_email_address(),
+                     survey.get_attending(), survey.get_guest_count())
+}
+
+fn rsvp_from_fields(first_name: &str, phone_number: &str, email_address: &str,
+                    attending: bool, guest_count: i32) -> std::result::Result<ClientRSVP, String> {
+    let phone_number = phone_number.trim();
+    let phone_number = if phone_number.is_empty() {
+        None
+    } else {
+        Some(normalize_phone_number(phone_number).map_err(|e| e.to_string())?)
+    };
+    let email_address = email_address.trim();
+    let rsvp = ClientRSVP {
+        first_name: first_name.trim().to_string(),
+        invite_id: None,
+        details: RsvpDetails {
+            phone_number,
+            email_address: (!email_address.is_empty()).then(|| email_address.to_string()),
+            attending,
+            guest_count: if attending { u8::try_from(guest_count).unwrap_or(1) } else { 1 }
+        }
+    };
+    if let Err(errors) = rsvp.validate_against(&RSVP_SCHEMA) {
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        return Err(messages.join("\n"));
+    }
+    Ok(rsvp)
+}
+
+/// Why an RSVP went unanswered
+#[derive(Debug)]
+enum SubmitError {
+    /// The server could not be reached, or the connection broke
+    Network(JsValue),
+    /// The server is overloaded or restarting
+    Unavailable,
+    /// The server turned the RSVP away, saying why
+    Refused(StatusCode, String),
+    /// The request could not be built, or the server's answer made no sense
+    Invalid(eyre::Report)
+}
+
+impl SubmitError {
+    fn worth_retrying(&self) -> bool {
+        match self {
+            SubmitError::Network(_) | SubmitError::Unavailable => true,
+            SubmitError::Refused(status, _) => *status == StatusCode::TOO_MANY_REQUESTS,
+            SubmitError::Invalid(_) => false
+        }
+    }
+}
+
+impl Display for SubmitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Network(_) => write!(f, "Your RSVP could not be sent. Please check your connection and try again."),
+            SubmitError::Unavailable => write!(f, "The server is busy. Please try again in a moment."),
+            SubmitError::Refused(_, message) => write!(f, "{}", message),
+            SubmitError::Invalid(e) => write!(f, "Something went wrong: {}", e)
+        }
+    }
+}
+
+// Far more than any ServerResponse needs
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+// Sent with the browser's fetch, since there are no sockets to connect with in WASM
+async fn send_rsvp(window: &web_sys::Window, origin: &str, rsvp: ClientRSVP) -> std::result::Result<ServerResponse, SubmitError> {
+    let request = rsvp_request(origin, rsvp).map_err(SubmitError::Invalid)?;
+    let request = fetch_request(request).await.map_err(SubmitError::Invalid)?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_request(&request)).await
+        .and_then(JsCast::dyn_into)
+        .map_err(SubmitError::Network)?;
+    let status = StatusCode::from_u16(response.status()).map_err(|e| SubmitError::Invalid(e.into()))?;
+    if status.is_server_error() {
+        return Err(SubmitError::Unavailable);
+    }
+    let body = response_bytes(&response).await.map_err(SubmitError::Network)?;
+    if !status.is_success() {
+        // Refusals are explained in plain text
+        return Err(SubmitError::Refused(status, String::from_utf8_lossy(&body).into_owned()));
+    }
+    ServerResponse::decode(Body::from(body), MAX_RESPONSE_BYTES).await.map_err(|e| SubmitError::Invalid(e.into()))
+}
+
+async fn fetch_request(request: Request<Body>) -> Result<web_sys::Request> {
+    let (parts, body) = request.into_parts();
+    let body = body::to_bytes(body).await?;
+    let headers = web_sys::Headers::new().map_err(js_error)?;
+    for (name, value) in &parts.headers {
+        headers.append(name.as_str(), value.to_str()?).map_err(js_error)?;
+    }
+    let mut init = web_sys::RequestInit::new();
+    init.method(parts.method.as_str())
+        .headers(&headers)
+        .body(Some(&js_sys::Uint8Array::from(&body[..])));
+    web_sys::Request::new_with_str_and_init(&parts.uri.to_string(), &init).map_err(js_error)
+}
+
+async fn response_bytes(response: &web_sys::Response) -> std::result::Result<Vec<u8>, JsValue> {
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+fn js_error(e: JsValue) -> eyre::Report {
+    eyre::eyre!("{:?}", e)
+}
+
+fn rsvp_request(origin: &str, rsvp: ClientRSVP) -> Result<Request<Body>> {
+    Ok(Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/{}", origin, PostPath::EnterRsvp.as_ref()))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(rsvp.encode()?)?)
+}
+
+fn describe_response(response: &ServerResponse) -> String {
+    String::from(match response {
+        ServerResponse::Success => "Thanks! Your RSVP is in.",
+        ServerResponse::Queued => "Thanks! Your RSVP will be recorded shortly.",
+        ServerResponse::AlreadyRSVPed(_) => "You already RSVPed.",
+        ServerResponse::NotInvited => "You are not on the guest list. Please ask the coordinator who linked you to this website.",
+        ServerResponse::InviteExpired => "Sorry, RSVPs are closed.",
+        ServerResponse::InviteIdRequired => "Several guests share your name. Please RSVP by SMS to the coordinator who linked you to this website.",
+        ServerResponse::NotYetRSVPed => "There is no RSVP to change."
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_fields_into_rsvp() {
+        let rsvp = rsvp_from_fields(" Alice ", "(412) 555-0123", "alice@example.com ", true, 3).unwrap();
+        assert_eq!(ClientRSVP {
+            first_name: String::from("Alice"),
+            invite_id: None,
+            details: RsvpDetails {
+                phone_number: Some(String::from("+14125550123")),
+                email_address: Some(String::from("alice@example.com")),
+                attending: true,
+                guest_count: 3
+            }
+        }, rsvp);
+    }
+
+    #[test]
+    fn optional_fields_left_blank() {
+        let rsvp = rsvp_from_fields("Bob", " ", "", false, 4).unwrap();
+        assert_eq!(None, rsvp.details.phone_number);
+        assert_eq!(None, rsvp.details.email_address);
+        assert!(!rsvp.details.attending);
+        assert_eq!(1, rsvp.details.guest_count);
+    }
+
+    #[test]
+    fn screen_after_response() {
+        assert_eq!(Screen::Recorded, Screen::after(&ServerResponse::Success));
+        assert_eq!(Screen::Recorded, Screen::after(&ServerResponse::Queued));
+        assert_eq!(Screen::AlreadyRSVPed, Screen::after(&ServerResponse::AlreadyRSVPed(1_662_089_400)));
+        assert_eq!(Screen::NotInvited, Screen::after(&ServerResponse::NotInvited));
+        assert_eq!(Screen::Form, Screen::after(&ServerResponse::InviteExpired));
+    }
+
+    #[test]
+    fn request_to_enter_rsvp() -> Result<()> {
+        let rsvp = rsvp_from_fields("Alice", "", "alice@example.com", true, 1).unwrap();
+        let request = rsvp_request("https://thebestofcmu.example", rsvp)?;
+        assert_eq!(Method::POST, request.method());
+        assert_eq!("https://thebestofcmu.example/enter-rsvp", request.uri().to_string());
+        assert_eq!("application/json", request.headers()[header::CONTENT_TYPE]);
+        Ok(())
+    }
+
+    #[test]
+    fn retry_after_transient_failures() {
+        assert!(SubmitError::Unavailable.worth_retrying());
+        let rate_limited = SubmitError::Refused(StatusCode::TOO_MANY_REQUESTS, String::from("Too many requests. Please try again later"));
+        assert!(rate_limited.worth_retrying());
+        assert_eq!("Too many requests. Please try again later", rate_limited.to_string());
+        assert!(!SubmitError::Refused(StatusCode::BAD_REQUEST, String::from("Unable to parse RSVP json")).worth_retrying());
+        assert!(!SubmitError::Invalid(eyre::eyre!("Bad json")).worth_retrying());
+    }
+
+    #[test]
+    fn every_problem_shown() {
+        assert_eq!(Err(String::from("phone_number may not contain 'c'")),
+                   rsvp_from_fields("Alice", "call me", "", true, 1));
+        assert_eq!(Err(String::from("first_name is required\nemail_address may not contain ' '")),
+                   rsvp_from_fields("", "", "a b@example.com", true, 1));
+    }
+}
@@ -1,31 +1,299 @@
 use std::fmt::{Display, Formatter};
-use std::time::SystemTime;
-use hyper::{Body, body};
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use hyper::Body;
+use hyper::body::HttpBody;
+use serde::{Deserialize, Deserializer, Serialize};
 use eyre::Result;
 
+/// The database identifier of an invitee
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct InviteId(pub i32);
+
+impl Display for InviteId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for InviteId {
+    type Err = eyre::Report;
+
+    fn from_str(id: &str) -> Result<Self> {
+        let id: i32 = id.trim().parse()
+            .map_err(|e| eyre::eyre!("Invalid invite ID {}: {}", id, e))?;
+        if id < 1 {
+            return Err(eyre::eyre!("Invalid invite ID {}: must be positive", id));
+        }
+        Ok(InviteId(id))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Invitee {
-    pub id: i32,
+    pub id: InviteId,
     pub first_name: String,
+    pub source: Option<String>,
+    /// Private to coordinators, never to be shown to invitees
+    pub admin_note: Option<String>,
+    /// When the invitee was added. Unknown for those added before this was recorded.
+    pub created_at: Option<SystemTime>,
     pub rsvp: Option<(RsvpDetails, SystemTime)>
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ClientRSVP {
     pub first_name: String,
+    /// Tells apart invitees sharing a first name, if the server allows that.
+    /// Required to update an RSVP, so that knowing a first name is not enough.
+    #[serde(default)]
+    pub invite_id: Option<InviteId>,
     pub details: RsvpDetails
 }
 
+/// Constraints on a single field of an RSVP
+#[derive(Clone, Copy)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    pub required: bool,
+    /// Most characters allowed
+    pub max_len: usize,
+    pub allowed_char: fn(char) -> bool
+}
+
+/// Constraints on every field of an RSVP, shared by the server and the client
+#[derive(Clone, Copy)]
+pub struct RsvpSchema {
+    pub first_name: FieldSchema,
+    pub phone_number: FieldSchema,
+    pub email_address: FieldSchema
+}
+
+pub const RSVP_SCHEMA: RsvpSchema = RsvpSchema {
+    first_name: FieldSchema {
+        name: "first_name",
+        required: true,
+        max_len: 32,
+        allowed_char: |c| c.is_alphabetic() || c == ' ' || c == '-' || c == '\''
+    },
+    phone_number: FieldSchema {
+        name: "phone_number",
+        required: false,
+        // Digits in the longest E.164 number
+        max_len: 15,
+        allowed_char: |c| c.is_ascii_digit()
+    },
+    email_address: FieldSchema {
+        name: "email_address",
+        required: false,
+        max_len: 128,
+        allowed_char: |c| c.is_ascii_graphic()
+    }
+};
+
+/// Why a field of an RSVP was rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldError {
+    Missing { field: &'static str },
+    TooLong { field: &'static str, max_len: usize },
+    DisallowedChar { field: &'static str, character: char },
+    /// The field is well-formed character by character, but not as a whole
+    Invalid { field: &'static str, message: &'static str },
+    /// An acceptance gave neither a phone number nor an email address
+    NoContactInfo
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldError::Missing { field } => write!(f, "{} is required", field),
+            FieldError::TooLong { field, max_len } => write!(f, "{} must be at most {} characters", field, max_len),
+            FieldError::DisallowedChar { field, character } => write!(f, "{} may not contain {:?}", field, character),
+            FieldError::Invalid { message, .. } => write!(f, "{}", message),
+            FieldError::NoContactInfo => write!(f, "A phone number or email address is required")
+        }
+    }
+}
+
+impl FieldSchema {
+    fn validate(&self, value: Option<&str>, errors: &mut Vec<FieldError>) {
+        let field = self.name;
+        let value = match value {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                if self.required {
+                    errors.push(FieldError::Missing { field });
+                }
+                return;
+            }
+        };
+        if value.chars().count() > self.max_len {
+            errors.push(FieldError::TooLong { field, max_len: self.max_len });
+        }
+        if let Some(character) = value.chars().find(|c| !(self.allowed_char)(*c)) {
+            errors.push(FieldError::DisallowedChar { field, character });
+        }
+    }
+}
+
+/// Normalizes a phone number to E.164, such as +14125550123. Spaces and the usual punctuation
+/// are dropped. Numbers without a country code are taken to be North American, as most
+/// invitees are, but other numbers need a leading + or 00 and their country code.
+pub fn normalize_phone_number(phone_number: &str) -> std::result::Result<String, FieldError> {
+    let schema = &RSVP_SCHEMA.phone_number;
+    let field = schema.name;
+    let phone_number = phone_number.trim();
+    let (international, number) = match phone_number.strip_prefix('+') {
+        Some(number) => (true, number),
+        None => (false, phone_number)
+    };
+    let mut digits = String::new();
+    for character in number.chars() {
+        if matches!(character, ' ' | '-' | '.' | '(' | ')') {
+            continue;
+        }
+        if !(schema.allowed_char)(character) {
+            return Err(FieldError::DisallowedChar { field, character });
+        }
+        digits.push(character);
+    }
+    let digits = if international {
+        digits
+    } else if let Some(number) = digits.strip_prefix("00") {
+        number.to_string()
+    } else if digits.len() == 10 && digits.starts_with(|c: char| ('2'..='9').contains(&c)) {
+        format!("1{}", digits)
+    } else if digits.len() == 11 && digits.starts_with('1') {
+        digits
+    } else {
+        return Err(FieldError::Invalid {
+            field,
+            message: "Invalid phone number. Numbers outside North America need a country code, such as +44"
+        });
+    };
+    if digits.len() > schema.max_len {
+        return Err(FieldError::TooLong { field, max_len: schema.max_len });
+    }
+    // Country codes never start with 0, and no full number is shorter than this
+    if digits.len() < 7 || digits.starts_with('0') {
+        return Err(FieldError::Invalid { field, message: "Invalid phone number" });
+    }
+    Ok(format!("+{}", digits))
+}
+
+/// Checks that an email address looks deliverable, without trying to deliver to it. This is
+/// stricter than RFC 5322 allows, refusing quoted local parts and addresses at IP literals.
+pub fn validate_email_address(email_address: &str) -> std::result::Result<(), FieldError> {
+    let invalid = FieldError::Invalid { field: RSVP_SCHEMA.email_address.name, message: "Invalid email address" };
+    let (local_part, domain) = email_address.rsplit_once('@').ok_or_else(|| invalid.clone())?;
+    let local_part_valid = !local_part.is_empty()
+        && local_part.split('.').all(|atom| {
+            !atom.is_empty() && atom.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+        });
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_valid = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        // Top-level domains are never numeric, so this is no IP address
+        && !labels[labels.len() - 1].chars().all(|c| c.is_ascii_digit());
+    if local_part_valid && domain_valid {
+        Ok(())
+    } else {
+        Err(invalid)
+    }
+}
+
+impl ClientRSVP {
+    /// Checks every field, yielding all the problems found. A phone number passes if it can be
+    /// normalized, so it should be normalized before it is stored.
+    pub fn validate_against(&self, schema: &RsvpSchema) -> std::result::Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        schema.first_name.validate(Some(&self.first_name), &mut errors);
+        match self.details.phone_number.as_deref() {
+            Some(phone_number) if !phone_number.is_empty() => {
+                if let Err(e) = normalize_phone_number(phone_number) {
+                    errors.push(e);
+                }
+            },
+            _ => schema.phone_number.validate(None, &mut errors)
+        }
+        let email_address = self.details.email_address.as_deref().filter(|email_address| !email_address.is_empty());
+        let errors_before = errors.len();
+        schema.email_address.validate(email_address, &mut errors);
+        if let (Some(email_address), true) = (email_address, errors.len() == errors_before) {
+            if let Err(e) = validate_email_address(email_address) {
+                errors.push(e);
+            }
+        }
+        let phone_number = self.details.phone_number.as_deref().filter(|phone_number| !phone_number.is_empty());
+        if self.details.attending && phone_number.is_none() && email_address.is_none() {
+            errors.push(FieldError::NoContactInfo);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RsvpDetails {
-    pub phone_number: Option<i64>,
-    pub email_address: Option<String>
+    /// In E.164 form, such as +14125550123, once normalized
+    #[serde(default, deserialize_with = "text_or_legacy_phone_number")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub phone_number: Option<String>,
+    pub email_address: Option<String>,
+    /// Whether the invitee is coming. Clients predating declines only ever sent acceptances.
+    #[serde(default = "attending_by_default")]
+    pub attending: bool,
+    /// How many people are coming, the invitee included
+    #[serde(default = "party_of_one")]
+    pub guest_count: u8
+}
+
+// Phone numbers were once sent as integers, and still are by old clients and queued RSVPs
+fn text_or_legacy_phone_number<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+    where D: Deserializer<'de> {
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TextOrInteger {
+        Text(String),
+        Integer(i64)
+    }
+    Ok(match Option::<TextOrInteger>::deserialize(deserializer)? {
+        Some(TextOrInteger::Text(phone_number)) => Some(phone_number),
+        Some(TextOrInteger::Integer(phone_number)) => {
+            let phone_number = phone_number.to_string();
+            Some(normalize_phone_number(&phone_number).unwrap_or(phone_number))
+        },
+        None => None
+    })
+}
+
+fn attending_by_default() -> bool {
+    true
+}
+
+fn party_of_one() -> u8 {
+    1
 }
 
 impl Display for RsvpDetails {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match (self.phone_number, Some("")) {
+        if self.attending {
+            write!(f, "Attending. Party of {}. ", self.guest_count)?;
+        } else {
+            write!(f, "Declined. ")?;
+        }
+        match (&self.phone_number, &self.email_address) {
             (None, None) => write!(f, "No contact info"),
             (Some(phone_no), None) => write!(f, "Phone number: {}", phone_no),
             (None, Some(email)) => write!(f, "Email address: {}", email),
@@ -36,15 +304,19 @@ impl Display for RsvpDetails {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PostPath {
-    EnterRsvp
+    EnterRsvp,
+    CancelRsvp,
+    /// Replaces the details of an RSVP, such as a mistyped phone number
+    UpdateRsvp
 }
 
 impl PostPath {
     pub fn from_str(path: &str) -> Option<Self> {
-        if path == "enter-rsvp" {
-            Some(PostPath::EnterRsvp)
-        } else {
-            None
+        match path {
+            "enter-rsvp" => Some(PostPath::EnterRsvp),
+            "cancel-rsvp" => Some(PostPath::CancelRsvp),
+            "update-rsvp" => Some(PostPath::UpdateRsvp),
+            _ => None
         }
     }
 }
@@ -52,34 +324,575 @@ impl PostPath {
 impl AsRef<str> for PostPath {
     fn as_ref(&self) -> &str {
         match self {
-            &PostPath::EnterRsvp => "enter-rsvp"
+            &PostPath::EnterRsvp => "enter-rsvp",
+            &PostPath::CancelRsvp => "cancel-rsvp",
+            &PostPath::UpdateRsvp => "update-rsvp"
         }
     }
 }
 
+/// Withdraws an RSVP, letting the invitee RSVP afresh
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClientCancellation {
+    pub first_name: String,
+    /// Required, so that knowing a first name is not enough. Optional only so that
+    /// cancellations without it are answered with InviteIdRequired.
+    #[serde(default)]
+    pub invite_id: Option<InviteId>
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ServerResponse {
     Success,
     NotInvited,
-    AlreadyRSVPed(u64)
+    /// The invitee RSVPed before, this many seconds after the Unix epoch
+    AlreadyRSVPed(u64),
+    InviteExpired,
+    /// Several invitees may share the name, so the RSVP must say which one it is for
+    InviteIdRequired,
+    /// The RSVP will be recorded once the database is reachable again
+    Queued,
+    /// There is no RSVP to cancel
+    NotYetRSVPed
+}
+
+impl ServerResponse {
+    /// Says the invitee already RSVPed at the given time, to the second
+    pub fn already_rsvped_at(time: SystemTime) -> Self {
+        let secs_since_epoch = time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+        ServerResponse::AlreadyRSVPed(secs_since_epoch)
+    }
+
+    /// When the invitee RSVPed before, if the response says they did
+    pub fn previous_rsvp_time(&self) -> Option<SystemTime> {
+        match self {
+            ServerResponse::AlreadyRSVPed(secs_since_epoch) => {
+                Some(SystemTime::UNIX_EPOCH + Duration::from_secs(*secs_since_epoch))
+            },
+            _ => None
+        }
+    }
+}
+
+/// Answers GET /rsvp-status, for invitees unsure whether they already RSVPed
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RsvpStatus {
+    NotInvited,
+    /// Several invitees may share the name, so the lookup must say which one it is for
+    InviteIdRequired,
+    NotYetRSVPed,
+    /// The RSVP, and when it was made in seconds since the Unix epoch
+    RSVPed(RsvpDetails, u64),
+    /// When the RSVP was made, in seconds since the Unix epoch, without what it says
+    RSVPedAt(u64)
+}
+
+impl RsvpStatus {
+    /// Leaves out the details of any RSVP, such as contact information, which a lookup
+    /// by first name alone must not reveal
+    pub fn without_details(self) -> Self {
+        match self {
+            RsvpStatus::RSVPed(_, time_registered) => RsvpStatus::RSVPedAt(time_registered),
+            other => other
+        }
+    }
+}
+
+/// JSON Schema for what POST /enter-rsvp takes, a ClientRSVP, and what it answers, a
+/// ServerResponse. Both are among the definitions, alongside the types they contain.
+#[cfg(feature = "schema")]
+pub fn api_schema() -> serde_json::Value {
+    let mut generator = schemars::gen::SchemaSettings::draft07().into_generator();
+    let request = generator.subschema_for::<ClientRSVP>();
+    let response = generator.subschema_for::<ServerResponse>();
+    serde_json::json!({
+        "$schema": generator.settings().meta_schema,
+        "title": "RSVP API",
+        "description": "POST /enter-rsvp takes a ClientRSVP and answers with a ServerResponse",
+        "anyOf": [request, response],
+        "definitions": generator.definitions()
+    })
+}
+
+/// How the body of an API request or response is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    /// More compact than JSON, for clients which ask for it
+    Cbor
+}
+
+impl BodyFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BodyFormat::Json => "application/json",
+            BodyFormat::Cbor => "application/cbor"
+        }
+    }
+
+    /// The format of a body with the given Content-Type, if it is one of ours
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        [BodyFormat::Json, BodyFormat::Cbor].into_iter()
+            .find(|format| media_type.eq_ignore_ascii_case(format.content_type()))
+    }
+
+    /// The first of our formats named in the Accept header. Quality values are not weighed
+    /// against each other, but a format with a quality of zero is never chosen.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(|media_range| {
+            let mut params = media_range.split(';');
+            let format = Self::from_content_type(params.next().unwrap_or_default())?;
+            let refused = params.any(|param| {
+                param.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            (!refused).then_some(format)
+        })
+    }
+}
+
+/// Why a body could not be decoded
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The body could not be read, as when the connection broke
+    Read(hyper::Error),
+    /// The body was longer than allowed, so was not read in full
+    TooLarge { max_bytes: usize },
+    Empty,
+    InvalidUtf8(std::str::Utf8Error),
+    InvalidJson(serde_json::Error),
+    InvalidCbor(serde_cbor::Error)
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Read(e) => write!(f, "Unable to read body: {}", e),
+            DecodeError::TooLarge { max_bytes } => write!(f, "Body is larger than {} bytes", max_bytes),
+            DecodeError::Empty => write!(f, "Body is empty"),
+            DecodeError::InvalidUtf8(e) => write!(f, "Body is not UTF-8: {}", e),
+            DecodeError::InvalidJson(e) => write!(f, "Body is not valid JSON: {}", e),
+            DecodeError::InvalidCbor(e) => write!(f, "Body is not valid CBOR: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Read(e) => Some(e),
+            DecodeError::InvalidUtf8(e) => Some(e),
+            DecodeError::InvalidJson(e) => Some(e),
+            DecodeError::InvalidCbor(e) => Some(e),
+            DecodeError::TooLarge { .. } | DecodeError::Empty => None
+        }
+    }
+}
+
+// Buffers the body, giving up once it proves longer than max_bytes. A Content-Length
+// over the limit is refused before anything is read.
+async fn read_body(mut body: Body, max_bytes: usize) -> std::result::Result<Vec<u8>, DecodeError> {
+    if body.size_hint().lower() > max_bytes as u64 {
+        return Err(DecodeError::TooLarge { max_bytes });
+    }
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(DecodeError::Read)?;
+        if buffer.len() + chunk.len() > max_bytes {
+            return Err(DecodeError::TooLarge { max_bytes });
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
 }
 
 macro_rules! encode_decode_as_http_body {
     ($typename:ident) => {
         impl $typename {
             pub fn encode(self) -> Result<Body> {
-                let string = serde_json::to_string(&self)?;
-                Ok(Body::from(string))
+                self.encode_as(BodyFormat::Json)
+            }
+
+            pub fn encode_as(self, format: BodyFormat) -> Result<Body> {
+                Ok(match format {
+                    BodyFormat::Json => Body::from(serde_json::to_string(&self)?),
+                    BodyFormat::Cbor => Body::from(serde_cbor::to_vec(&self)?)
+                })
             }
 
-            pub async fn decode(body: Body) -> Result<Self> {
-                let bytes = body::to_bytes(body).await?;
-                let string = std::str::from_utf8(&bytes)?;
-                Ok(serde_json::from_str(string)?)
+            /// Reads and parses the JSON body, which may be at most max_bytes long
+            pub async fn decode(body: Body, max_bytes: usize) -> std::result::Result<Self, DecodeError> {
+                Self::decode_as(body, max_bytes, BodyFormat::Json).await
+            }
+
+            pub async fn decode_as(body: Body,
+                                   max_bytes: usize,
+                                   format: BodyFormat) -> std::result::Result<Self, DecodeError> {
+                let bytes = read_body(body, max_bytes).await?;
+                if bytes.is_empty() {
+                    return Err(DecodeError::Empty);
+                }
+                match format {
+                    BodyFormat::Json => {
+                        let string = std::str::from_utf8(&bytes).map_err(DecodeError::InvalidUtf8)?;
+                        serde_json::from_str(string).map_err(DecodeError::InvalidJson)
+                    },
+                    BodyFormat::Cbor => serde_cbor::from_slice(&bytes).map_err(DecodeError::InvalidCbor)
+                }
             }
         }
     }
 }
 
 encode_decode_as_http_body!(ClientRSVP);
+encode_decode_as_http_body!(ClientCancellation);
 encode_decode_as_http_body!(ServerResponse);
+encode_decode_as_http_body!(RsvpStatus);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsvp(first_name: &str, phone_number: Option<&str>, email_address: Option<&str>) -> ClientRSVP {
+        ClientRSVP {
+            first_name: String::from(first_name),
+            invite_id: None,
+            details: RsvpDetails {
+                phone_number: phone_number.map(String::from),
+                email_address: email_address.map(String::from),
+                attending: true,
+                guest_count: 1
+            }
+        }
+    }
+
+    async fn decode_rsvp(body: impl Into<Body>, max_bytes: usize) -> std::result::Result<ClientRSVP, DecodeError> {
+        ClientRSVP::decode(body.into(), max_bytes).await
+    }
+
+    #[async_std::test]
+    async fn decode_round_trip() -> Result<()> {
+        let rsvp = rsvp("Alice", None, Some("alice@example.com"));
+        let decoded = ClientRSVP::decode(rsvp.clone().encode()?, 1024).await?;
+        assert_eq!(rsvp, decoded);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cbor_round_trip() -> Result<()> {
+        let rsvp = rsvp("Alice", Some("4125550123"), None);
+        let encoded = hyper::body::to_bytes(rsvp.clone().encode_as(BodyFormat::Cbor)?).await?;
+        let json = serde_json::to_string(&rsvp)?;
+        assert!(encoded.len() < json.len(), "{} bytes", encoded.len());
+        assert_eq!(rsvp, ClientRSVP::decode_as(Body::from(encoded), 1024, BodyFormat::Cbor).await?);
+
+        for response in [ServerResponse::Success, ServerResponse::AlreadyRSVPed(1_662_089_400)] {
+            let encoded = response.clone().encode_as(BodyFormat::Cbor)?;
+            assert_eq!(response, ServerResponse::decode_as(encoded, 1024, BodyFormat::Cbor).await?);
+        }
+        // JSON is not CBOR
+        let decoded = ClientRSVP::decode_as(Body::from(json), 1024, BodyFormat::Cbor).await;
+        assert!(matches!(decoded, Err(DecodeError::InvalidCbor(_))), "{:?}", decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn negotiate_format() {
+        assert_eq!(Some(BodyFormat::Json), BodyFormat::from_content_type("application/json"));
+        assert_eq!(Some(BodyFormat::Json), BodyFormat::from_content_type("Application/JSON; charset=utf-8"));
+        assert_eq!(Some(BodyFormat::Cbor), BodyFormat::from_content_type("application/cbor"));
+        assert_eq!(None, BodyFormat::from_content_type("text/plain"));
+
+        assert_eq!(Some(BodyFormat::Cbor), BodyFormat::from_accept("application/cbor, application/json;q=0.5"));
+        assert_eq!(Some(BodyFormat::Json), BodyFormat::from_accept("text/html, application/json, application/cbor"));
+        assert_eq!(Some(BodyFormat::Json), BodyFormat::from_accept("application/cbor;q=0, application/json"));
+        assert_eq!(None, BodyFormat::from_accept("*/*"));
+    }
+
+    #[async_std::test]
+    async fn decode_errors() -> Result<()> {
+        let json = serde_json::to_string(&rsvp("Alice", None, Some("alice@example.com")))?;
+        assert!(decode_rsvp(json.clone(), json.len()).await.is_ok());
+        match decode_rsvp(json.clone(), json.len() - 1).await {
+            Err(DecodeError::TooLarge { max_bytes }) => assert_eq!(json.len() - 1, max_bytes),
+            other => panic!("{:?}", other)
+        }
+        assert!(matches!(decode_rsvp("", 1024).await, Err(DecodeError::Empty)));
+        assert!(matches!(decode_rsvp(vec![b'{', 0xff, b'}'], 1024).await, Err(DecodeError::InvalidUtf8(_))));
+        for invalid in [" ", "{", r#"{"first_name": "Alice"}"#] {
+            assert!(matches!(decode_rsvp(invalid, 1024).await, Err(DecodeError::InvalidJson(_))), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    fn validate(rsvp: ClientRSVP) -> std::result::Result<(), Vec<FieldError>> {
+        rsvp.validate_against(&RSVP_SCHEMA)
+    }
+
+    const EMAIL: Option<&str> = Some("alice@example.com");
+
+    #[test]
+    fn first_name_limits() {
+        assert_eq!(Ok(()), validate(rsvp(&"a".repeat(32), None, EMAIL)));
+        assert_eq!(Ok(()), validate(rsvp("Zoë O'Brien-Smith", None, EMAIL)));
+        assert_eq!(Err(vec![FieldError::TooLong { field: "first_name", max_len: 32 }]),
+                   validate(rsvp(&"a".repeat(33), None, EMAIL)));
+        assert_eq!(Err(vec![FieldError::Missing { field: "first_name" }]), validate(rsvp("", None, EMAIL)));
+        assert_eq!(Err(vec![FieldError::DisallowedChar { field: "first_name", character: '<' }]),
+                   validate(rsvp("<b>", None, EMAIL)));
+    }
+
+    #[test]
+    fn phone_number_limits() {
+        assert_eq!(Ok(()), validate(rsvp("Alice", Some("+999999999999999"), None)));
+        assert_eq!(Ok(()), validate(rsvp("Alice", Some("(412) 555-0123"), None)));
+        assert_eq!(Ok(()), validate(rsvp("Alice", Some(""), EMAIL)));
+        assert_eq!(Err(vec![FieldError::TooLong { field: "phone_number", max_len: 15 }]),
+                   validate(rsvp("Alice", Some("+1000000000000000"), None)));
+        assert_eq!(Err(vec![FieldError::DisallowedChar { field: "phone_number", character: 'x' }]),
+                   validate(rsvp("Alice", Some("412-555-0123 x12"), None)));
+    }
+
+    #[test]
+    fn normalize_north_american_phone_numbers() {
+        for phone_number in ["4125550123", "(412) 555-0123", "412.555.0123", "1-412-555-0123",
+                             "+1 412 555 0123", "001 412 555 0123", " +14125550123 "] {
+            assert_eq!(Ok(String::from("+14125550123")), normalize_phone_number(phone_number), "{}", phone_number);
+        }
+    }
+
+    #[test]
+    fn normalize_international_phone_numbers() {
+        assert_eq!(Ok(String::from("+442079460018")), normalize_phone_number("+44 20 7946 0018"));
+        assert_eq!(Ok(String::from("+442079460018")), normalize_phone_number("0044 20 7946 0018"));
+        assert_eq!(Ok(String::from("+81312345678")), normalize_phone_number("+81-3-1234-5678"));
+        assert_eq!(Ok(String::from("+919876543210")), normalize_phone_number("+91 98765 43210"));
+    }
+
+    #[test]
+    fn reject_invalid_phone_numbers() {
+        assert_eq!(Err(FieldError::DisallowedChar { field: "phone_number", character: 'c' }),
+                   normalize_phone_number("call me"));
+        assert_eq!(Err(FieldError::DisallowedChar { field: "phone_number", character: 'O' }),
+                   normalize_phone_number("412-555-O123"));
+        assert_eq!(Err(FieldError::TooLong { field: "phone_number", max_len: 15 }),
+                   normalize_phone_number(&format!("+{}", "1".repeat(40))));
+        // Without a country code, only North American numbers are understood
+        assert!(matches!(normalize_phone_number("020 7946 0018"), Err(FieldError::Invalid { .. })));
+        assert!(matches!(normalize_phone_number("555-0123"), Err(FieldError::Invalid { .. })));
+        assert!(matches!(normalize_phone_number("+0123456789"), Err(FieldError::Invalid { .. })));
+        assert!(matches!(normalize_phone_number("+12"), Err(FieldError::Invalid { .. })));
+        assert!(matches!(normalize_phone_number("+"), Err(FieldError::Invalid { .. })));
+    }
+
+    #[test]
+    fn legacy_integer_phone_numbers() -> Result<()> {
+        let details: RsvpDetails = serde_json::from_str(r#"{"phone_number":4125550123,"email_address":null}"#)?;
+        assert_eq!(Some(String::from("+14125550123")), details.phone_number);
+        let details: RsvpDetails = serde_json::from_str(r#"{"phone_number":"+442079460018","email_address":null}"#)?;
+        assert_eq!(Some(String::from("+442079460018")), details.phone_number);
+        let details: RsvpDetails = serde_json::from_str(r#"{"email_address":null}"#)?;
+        assert_eq!(None, details.phone_number);
+        Ok(())
+    }
+
+    #[test]
+    fn email_address_limits() {
+        let at_limit = format!("{}@example.com", "a".repeat(128 - "@example.com".len()));
+        assert_eq!(Ok(()), validate(rsvp("Alice", None, Some(&at_limit))));
+        assert_eq!(Ok(()), validate(rsvp("Alice", Some("+14125550123"), Some(""))));
+        assert_eq!(Err(vec![FieldError::TooLong { field: "email_address", max_len: 128 }]),
+                   validate(rsvp("Alice", None, Some(&format!("a{}", at_limit)))));
+        assert_eq!(Err(vec![FieldError::DisallowedChar { field: "email_address", character: ' ' }]),
+                   validate(rsvp("Alice", None, Some("alice @example.com"))));
+    }
+
+    #[test]
+    fn valid_email_addresses() {
+        for email_address in ["alice@example.com", "alice.o'brien+rsvp@mail.example.co.uk", "a@b.cd",
+                              "ALICE@EXAMPLE.COM", "user_1@sub-domain.example.org"] {
+            assert_eq!(Ok(()), validate_email_address(email_address), "{}", email_address);
+        }
+    }
+
+    #[test]
+    fn malformed_email_addresses() {
+        for email_address in ["not-an-email", "@example.com", "alice@", "alice@example", "alice@@example.com",
+                              "alice..b@example.com", ".alice@example.com", "alice@example..com",
+                              "alice@-example.com", "alice@example-.com", "alice@192.168.0.1", "alice@exa_mple.com",
+                              "alice(comment)@example.com"] {
+            assert_eq!(Err(FieldError::Invalid { field: "email_address", message: "Invalid email address" }),
+                       validate_email_address(email_address), "{}", email_address);
+        }
+        assert_eq!(Err(vec![FieldError::Invalid { field: "email_address", message: "Invalid email address" }]),
+                   validate(rsvp("Alice", None, Some("not-an-email"))));
+        assert_eq!("Invalid email address", validate(rsvp("Alice", None, Some("not-an-email"))).unwrap_err()[0].to_string());
+    }
+
+    #[test]
+    fn contact_info_required_to_attend() {
+        assert_eq!(Err(vec![FieldError::NoContactInfo]), validate(rsvp("Alice", None, None)));
+        assert_eq!(Err(vec![FieldError::NoContactInfo]), validate(rsvp("Alice", Some(""), Some(""))));
+        assert_eq!("A phone number or email address is required", FieldError::NoContactInfo.to_string());
+        assert_eq!(Ok(()), validate(rsvp("Alice", Some("+14125550123"), None)));
+        // Nobody needs reaching about an event they are not coming to
+        let mut declined = rsvp("Alice", None, None);
+        declined.details.attending = false;
+        assert_eq!(Ok(()), validate(declined));
+    }
+
+    #[test]
+    fn every_problem_reported() {
+        let errors = validate(rsvp("", Some("-1"), Some("a\tb"))).unwrap_err();
+        assert_eq!(3, errors.len());
+        assert_eq!("first_name is required", errors[0].to_string());
+    }
+
+    #[test]
+    fn already_rsvped_time_round_trip() -> Result<()> {
+        let rsvped_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_400);
+        let response = ServerResponse::already_rsvped_at(rsvped_at);
+        assert_eq!(ServerResponse::AlreadyRSVPed(1_662_089_400), response);
+        assert_eq!(Some(rsvped_at), response.previous_rsvp_time());
+
+        let json = serde_json::to_string(&response)?;
+        assert_eq!(r#"{"AlreadyRSVPed":1662089400}"#, json);
+        let decoded: ServerResponse = serde_json::from_str(&json)?;
+        assert_eq!(Some(rsvped_at), decoded.previous_rsvp_time());
+        Ok(())
+    }
+
+    #[test]
+    fn previous_rsvp_time_to_the_second() {
+        let rsvped_at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_662_089_400_750);
+        assert_eq!(Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_400)),
+                   ServerResponse::already_rsvped_at(rsvped_at).previous_rsvp_time());
+        assert_eq!(None, ServerResponse::Success.previous_rsvp_time());
+        assert_eq!(None, ServerResponse::NotInvited.previous_rsvp_time());
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn api_schema_definitions() -> Result<()> {
+        use std::collections::BTreeMap;
+        use schemars::schema::{RootSchema, Schema};
+
+        let schema = api_schema();
+        let definitions: BTreeMap<String, Schema> = serde_json::from_value(schema["definitions"].clone())?;
+        assert!(definitions.contains_key("ServerResponse"));
+        let root: RootSchema = serde_json::from_value(schema.clone())?;
+        assert!(root.schema.subschemas.is_some());
+
+        let object = |name: &str| {
+            let object = &schema["definitions"][name];
+            let properties: Vec<&String> = object["properties"].as_object().unwrap().keys().collect();
+            let required: Vec<&str> = object["required"].as_array()
+                .map(|required| required.iter().map(|name| name.as_str().unwrap()).collect())
+                .unwrap_or_default();
+            (properties, required)
+        };
+        let (properties, required) = object("ClientRSVP");
+        assert_eq!(vec!["details", "first_name", "invite_id"], properties);
+        assert_eq!(vec!["details", "first_name"], required);
+        let (properties, required) = object("RsvpDetails");
+        assert_eq!(vec!["attending", "email_address", "guest_count", "phone_number"], properties);
+        // Every detail may be left out
+        assert!(required.is_empty());
+        assert_eq!("string", schema["definitions"]["RsvpDetails"]["properties"]["phone_number"]["type"][0]);
+
+        let response = serde_json::to_string(&schema["definitions"]["ServerResponse"])?;
+        for variant in ["Success", "NotInvited", "AlreadyRSVPed", "InviteExpired", "Queued"] {
+            assert!(response.contains(variant), "{}", variant);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invite_id() -> Result<()> {
+        assert_eq!(InviteId(1), "1".parse()?);
+        assert_eq!(InviteId(42), " 42\n".parse()?);
+        assert_eq!("42", InviteId(42).to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_invite_id() {
+        for id in ["", "0", "-3", "4.5", "forty-two", "99999999999"] {
+            assert!(id.parse::<InviteId>().is_err(), "{}", id);
+        }
+    }
+
+    fn details(phone_number: Option<&str>, email_address: Option<&str>) -> RsvpDetails {
+        RsvpDetails { phone_number: phone_number.map(String::from), email_address: email_address.map(String::from), attending: true, guest_count: 1 }
+    }
+
+    #[test]
+    fn post_paths() {
+        for path in [PostPath::EnterRsvp, PostPath::CancelRsvp, PostPath::UpdateRsvp] {
+            assert_eq!(Some(path), PostPath::from_str(path.as_ref()));
+        }
+        assert_eq!(Some(PostPath::CancelRsvp), PostPath::from_str("cancel-rsvp"));
+        assert_eq!(Some(PostPath::UpdateRsvp), PostPath::from_str("update-rsvp"));
+        assert_eq!(None, PostPath::from_str("delete-rsvp"));
+    }
+
+    #[test]
+    fn display_no_contact_info() {
+        assert_eq!("Attending. Party of 1. No contact info", details(None, None).to_string());
+    }
+
+    #[test]
+    fn display_phone_number() {
+        assert_eq!("Attending. Party of 1. Phone number: +14125550123", details(Some("+14125550123"), None).to_string());
+    }
+
+    #[test]
+    fn display_email_address() {
+        assert_eq!("Attending. Party of 1. Email address: alice@example.com",
+                   details(None, Some("alice@example.com")).to_string());
+    }
+
+    #[test]
+    fn display_phone_number_and_email_address() {
+        assert_eq!("Attending. Party of 1. Phone number: +14125550123\n Email address: alice@example.com",
+                   details(Some("+14125550123"), Some("alice@example.com")).to_string());
+    }
+
+    #[test]
+    fn display_declined() {
+        let mut declined = details(Some("+14125550123"), None);
+        declined.attending = false;
+        assert_eq!("Declined. Phone number: +14125550123", declined.to_string());
+    }
+
+    #[test]
+    fn display_party_size() {
+        let mut party = details(None, Some("alice@example.com"));
+        party.guest_count = 3;
+        assert_eq!("Attending. Party of 3. Email address: alice@example.com", party.to_string());
+    }
+
+    #[test]
+    fn attendance_round_trip() -> Result<()> {
+        for attending in [true, false] {
+            let mut rsvp = rsvp("Alice", Some("+14125550123"), None);
+            rsvp.details.attending = attending;
+            let json = serde_json::to_string(&rsvp)?;
+            assert!(json.contains(&format!(r#""attending":{}"#, attending)), "{}", json);
+            assert_eq!(rsvp, serde_json::from_str(&json)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn attending_unless_declined() -> Result<()> {
+        let json = r#"{"first_name":"Alice","details":{"phone_number":4125550123,"email_address":null}}"#;
+        let rsvp: ClientRSVP = serde_json::from_str(json)?;
+        assert!(rsvp.details.attending);
+        assert_eq!(1, rsvp.details.guest_count);
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+fn main() {
+    // Migrations are embedded at compile time, so changes to them need a rebuild
+    println!("cargo:rerun-if-changed=migrations");
+}
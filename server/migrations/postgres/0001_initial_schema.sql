@@ -0,0 +1,45 @@
+-- Databases from before migrations already have some of these tables, perhaps in
+-- older shapes, so every statement is safe to run over them
+
+-- Identities start after the default event, which is inserted by hand
+CREATE TABLE IF NOT EXISTS "events" (
+  "id" INT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY (START WITH 2),
+  "slug" VARCHAR(32) NOT NULL,
+  "title_html" TEXT NULL,
+  "details_html" TEXT NULL,
+  CONSTRAINT "slug_uniqueness" UNIQUE ("slug")
+);
+INSERT INTO "events" ("id", "slug") VALUES (1, 'default') ON CONFLICT DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS "invited" (
+  "id" INT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY,
+  "first_name" VARCHAR(32) NOT NULL
+);
+-- Invitees from before events existed belong to the default event
+ALTER TABLE "invited" ADD COLUMN IF NOT EXISTS "event_id" INT NOT NULL DEFAULT 1
+  REFERENCES "events" ("id");
+-- First names are unique within, not across, events
+ALTER TABLE "invited" DROP CONSTRAINT IF EXISTS "first_name_uniqueness";
+ALTER TABLE "invited" ADD COLUMN IF NOT EXISTS "source" VARCHAR(32) NULL;
+ALTER TABLE "invited" ADD COLUMN IF NOT EXISTS "expires_at" BIGINT NULL;
+ALTER TABLE "invited" ADD COLUMN IF NOT EXISTS "admin_note" VARCHAR(256) NULL;
+
+CREATE TABLE IF NOT EXISTS "rsvps" (
+  "first_name" INT NOT NULL,
+  "phone_no" VARCHAR(16) NULL,
+  "email_address" VARCHAR(128) NULL,
+  "time_registered" BIGINT NOT NULL,
+  CONSTRAINT "first_name_uniqueness" UNIQUE ("first_name"),
+  CONSTRAINT "first_name_integrity" FOREIGN KEY ("first_name") REFERENCES "invited" ("id")
+);
+-- Phone numbers were once stored as integers, all of them North American
+-- without a country code, or else starting with one
+DO $$ BEGIN
+  IF EXISTS (SELECT 1 FROM "information_schema"."columns"
+             WHERE "table_name" = 'rsvps' AND "column_name" = 'phone_no' AND "data_type" = 'bigint') THEN
+    ALTER TABLE "rsvps" ALTER COLUMN "phone_no" TYPE VARCHAR(16) USING CASE
+      WHEN length("phone_no"::TEXT) = 10 THEN '+1' || "phone_no"::TEXT
+      ELSE '+' || "phone_no"::TEXT
+    END;
+  END IF;
+END $$;
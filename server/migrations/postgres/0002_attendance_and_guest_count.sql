@@ -0,0 +1,3 @@
+-- RSVPs from before declines could be recorded were all acceptances
+ALTER TABLE "rsvps" ADD COLUMN IF NOT EXISTS "attending" BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE "rsvps" ADD COLUMN IF NOT EXISTS "guest_count" SMALLINT NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- Names differing only in case belong to the same invitee. Whether names must be unique
+-- is configured, so the index on lowercased names replacing this is made after migrating.
+ALTER TABLE "invited" DROP CONSTRAINT IF EXISTS "event_first_name_uniqueness";
@@ -0,0 +1,2 @@
+-- Seconds since the Unix epoch, like the other times. Unknown for invitees added before.
+ALTER TABLE "invited" ADD COLUMN IF NOT EXISTS "created_at" BIGINT NULL;
@@ -0,0 +1,27 @@
+-- IDs start after the default event, which is inserted by hand
+CREATE TABLE "events" (
+  "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+  "slug" VARCHAR(32) NOT NULL,
+  "title_html" TEXT NULL,
+  "details_html" TEXT NULL,
+  CONSTRAINT "slug_uniqueness" UNIQUE ("slug")
+);
+INSERT INTO "events" ("id", "slug") VALUES (1, 'default');
+
+CREATE TABLE "invited" (
+  "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+  "first_name" VARCHAR(32) NOT NULL,
+  "event_id" INT NOT NULL REFERENCES "events" ("id"),
+  "source" VARCHAR(32) NULL,
+  "expires_at" BIGINT NULL,
+  "admin_note" VARCHAR(256) NULL
+);
+
+CREATE TABLE "rsvps" (
+  "first_name" INT NOT NULL,
+  "phone_no" VARCHAR(16) NULL,
+  "email_address" VARCHAR(128) NULL,
+  "time_registered" BIGINT NOT NULL,
+  CONSTRAINT "first_name_uniqueness" UNIQUE ("first_name"),
+  CONSTRAINT "first_name_integrity" FOREIGN KEY ("first_name") REFERENCES "invited" ("id")
+);
@@ -0,0 +1,2 @@
+ALTER TABLE "rsvps" ADD COLUMN "attending" BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE "rsvps" ADD COLUMN "guest_count" SMALLINT NOT NULL DEFAULT 1;
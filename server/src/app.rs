@@ -18,34 +18,117 @@
  */
 
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use async_std::channel::{self, Receiver};
+use async_std::future;
+use async_std::prelude::FutureExt;
 use async_std::sync::Arc;
 use async_std::net::TcpListener;
+use async_std::task;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use async_std::path::Path;
 use eyre::Result;
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use hyper::body::HttpBody;
+use hyper::{header, Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::HeaderValue;
 use hyper::http::{request, version};
+use hyper::server::Builder;
 use hyper::service::{make_service_fn, service_fn};
 use rustls::ServerConfig;
-use thebestofcmu_common::{ClientRSVP, PostPath};
-use crate::database::Database;
+use serde::Serialize;
+use time::{OffsetDateTime, UtcOffset};
+use time::format_description::well_known::Rfc3339;
+use thebestofcmu_common::{normalize_phone_number, BodyFormat, ClientCancellation, ClientRSVP, DecodeError, FieldError, InviteId, PostPath, RSVP_SCHEMA, ServerResponse};
+use crate::connection_limit::ConnectionTracker;
+use crate::database::{self, Database};
+use crate::deadline::Deadline;
+use crate::event::{self, DEFAULT_EVENT_ID, Event};
+use crate::forwarded::{ForwardedContext, PeerAddress};
 use crate::method::AllowedMethod;
-use crate::website::Website;
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+use crate::rsvp_queue::{QueuedRsvp, RsvpQueue};
+use crate::website::{self, Asset, Website};
 
 pub struct App {
     pub database: Database,
-    pub website: Website
+    pub website: Website,
+    pub request_timeout: Duration,
+    pub max_connections_per_ip: usize,
+    pub max_header_count: usize,
+    pub max_header_bytes: usize,
+    pub max_body_bytes: usize,
+    /// Lowercase host names which may be requested. Empty allows any host.
+    pub allowed_hosts: Vec<String>,
+    pub rsvp_queue: Option<Arc<RsvpQueue>>,
+    pub utc_offset: UtcOffset,
+    pub rsvp_deadline: Option<OffsetDateTime>,
+    /// Rejects RSVPs which did not arrive over HTTPS
+    pub require_https_for_rsvp: bool,
+    /// Proxies whose X-Forwarded-For, X-Forwarded-Proto and X-Forwarded-Host are believed
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Indent JSON responses for easier debugging
+    pub pretty_json: bool,
+    pub max_party_size: u8,
+    /// Limits how often each client may RSVP, cancel or look up an RSVP
+    pub rsvp_rate_limiter: RateLimiter,
+    /// Origins of other sites whose pages may call the API. Same-origin pages need not be listed.
+    pub cors_allowed_origins: Vec<String>,
+    /// Sent with every response, unless the response sets its own
+    pub content_security_policy: HeaderValue,
+    pub metrics: Arc<Metrics>,
+    /// Whether /metrics is answered alongside the site. Unset when metrics have an address of their own.
+    pub serves_metrics: bool,
+    /// Whether the server terminates TLS itself. Set when the server starts.
+    pub serves_https: bool,
+    /// Set once the schema is up to date. Until then, only /readyz is answered.
+    pub schema_ready: Arc<AtomicBool>,
+    /// Set once shutdown begins, after which requests are turned away
+    pub shutting_down: Arc<AtomicBool>,
+    /// How long shutdown waits for requests in flight before dropping them
+    pub shutdown_timeout: Duration
 }
 
+const RESTARTING_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>Restarting</title></head>
+<body>
+<p>The server is restarting. Please try again in a few seconds.</p>
+</body>
+</html>
+"#;
+
+// Hyper cannot buffer less than this
+const MIN_HEADER_BYTES: usize = 8192;
+
+// A day, so a redeployed asset reaches every visitor by the next
+const ASSET_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+const CORS_ALLOWED_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+
+// Browsers cap this anyway, Chrome at two hours
+const CORS_MAX_AGE_SECS: u64 = 2 * 60 * 60;
+
+// One line per request is logged here, so the access log can be filtered apart
+const ACCESS_LOG_TARGET: &str = "thebestofcmu::access";
+
 macro_rules! start_server_using {
     ($app:expr, $shutdown_future:expr, $listener:expr) => {
-        Server::builder($listener)
+        $app.with_header_limits(Server::builder($listener))
             .executor(compat::HyperExecutor)
-            .serve(make_service_fn(move |_| {
+            .serve(make_service_fn(move |connection| {
+                let peer_ip = compat::Peer::peer_ip(connection);
                 let app = $app.clone();
-                async {
-                    Ok::<_, eyre::Report>(service_fn(move |request: Request<Body>| {
+                async move {
+                    Ok::<_, eyre::Report>(service_fn(move |mut request: Request<Body>| {
                         let app = app.clone();
+                        if let Some(peer_ip) = peer_ip {
+                            request.extensions_mut().insert(PeerAddress(peer_ip));
+                        }
                         async move { (&app).handle_request(request).await }
                     }))
                 }
@@ -56,64 +139,430 @@ macro_rules! start_server_using {
 }
 
 impl App {
-    pub async fn start_server<F>(self,
+    pub async fn start_server<F>(mut self,
                                  socket: SocketAddr,
                                  tls: Option<Arc<ServerConfig>>,
                                  shutdown_future: F) -> Result<()>
         where F: Future<Output=()> {
 
+        if self.max_header_bytes < MIN_HEADER_BYTES {
+            return Err(eyre::eyre!("max_header_bytes must be at least {}", MIN_HEADER_BYTES));
+        }
+        let connection_tracker = ConnectionTracker::new(self.max_connections_per_ip, self.trusted_proxies.clone());
+        self.serves_https = tls.is_some();
+        let (shutdown_future, shutdown_began) = notify_shutdown(self.flag_shutdown(shutdown_future));
+        let shutdown_timeout = self.shutdown_timeout;
         let app = Arc::new(self);
 
         let listener = TcpListener::bind(&socket).await?;
-        let listener = compat::HyperListener::new(&listener);
+        let listener = compat::HyperListener::new(&listener, connection_tracker);
         log::info!("Bound to socket {}", socket);
 
-        Ok(if let Some(tls) = tls {
-            start_server_using!(app, shutdown_future, tls::TlsAcceptor::new(tls, listener))
-        } else {
-            start_server_using!(app, shutdown_future, listener)
-        }?)
+        let served = async {
+            Ok::<_, eyre::Report>(if let Some(tls) = tls {
+                start_server_using!(app, shutdown_future, tls::TlsAcceptor::new(tls, listener))
+            } else {
+                start_server_using!(app, shutdown_future, listener)
+            }?)
+        };
+        Self::drain_within(served, shutdown_began, shutdown_timeout).await
+    }
+
+    /// Serves plain HTTP on a Unix domain socket, removing the socket file once shut down
+    #[cfg(unix)]
+    pub async fn start_unix_server<F>(self, path: &Path, shutdown_future: F) -> Result<()>
+        where F: Future<Output=()> {
+
+        if self.max_header_bytes < MIN_HEADER_BYTES {
+            return Err(eyre::eyre!("max_header_bytes must be at least {}", MIN_HEADER_BYTES));
+        }
+        let (shutdown_future, shutdown_began) = notify_shutdown(self.flag_shutdown(shutdown_future));
+        let shutdown_timeout = self.shutdown_timeout;
+        let app = Arc::new(self);
+
+        let listener = UnixListener::bind(path).await?;
+        log::info!("Bound to Unix socket {}", path.display());
+        let served = async {
+            Ok::<_, eyre::Report>(start_server_using!(app, shutdown_future, compat::UnixHyperListener::new(&listener))?)
+        };
+        let served = Self::drain_within(served, shutdown_began, shutdown_timeout).await;
+
+        drop(listener);
+        match async_std::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("Unable to remove Unix socket {}: {}", path.display(), e);
+            },
+            _ => {}
+        }
+        served
+    }
+
+    fn flag_shutdown<F>(&self, shutdown_future: F) -> impl Future<Output=()>
+        where F: Future<Output=()> {
+
+        let shutting_down = self.shutting_down.clone();
+        async move {
+            shutdown_future.await;
+            shutting_down.store(true, Ordering::Release);
+        }
+    }
+
+    // Hyper waits for every request in flight before it stops, so one which never
+    // finishes would keep the server from stopping. They are dropped after the timeout.
+    async fn drain_within<S>(served: S, shutdown_began: Receiver<()>, timeout: Duration) -> Result<()>
+        where S: Future<Output=Result<()>> {
+
+        let drained = async {
+            served.await?;
+            log::info!("Shut down cleanly");
+            Ok::<_, eyre::Report>(())
+        };
+        let forced = async {
+            if shutdown_began.recv().await.is_err() {
+                // The server stopped without being shut down, so drained finishes first
+                return future::pending().await;
+            }
+            task::sleep(timeout).await;
+            log::warn!("Requests still in flight after {} seconds. Forcing shutdown", timeout.as_secs_f64());
+            Ok(())
+        };
+        drained.race(forced).await
+    }
+
+    // Hyper answers requests with too many header bytes itself. It also caps HTTP/1
+    // requests at 100 headers, but the configured count is checked in route_request.
+    fn with_header_limits<I, E>(&self, builder: Builder<I, E>) -> Builder<I, E> {
+        builder
+            .http1_max_buf_size(self.max_header_bytes)
+            .http2_max_header_list_size(u32::try_from(self.max_header_bytes).unwrap_or(u32::MAX))
     }
 
     async fn handle_request(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let started = Instant::now();
+        let (parts, body) = request.into_parts();
+        let method = parts.method.clone();
+        let path = parts.uri.path().to_string();
+        let version = parts.version;
+        let allowed_origin = self.allowed_origin(&parts.headers).cloned();
+        let forwarded = ForwardedContext::extract(&parts, self.serves_https, &self.trusted_proxies);
+        let client_ip = forwarded.client_ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+
+        let mut response = match self.route_request(Request::from_parts(parts, body), forwarded).await {
+            Ok(response) => response,
+            Err(e) => {
+                log::error!(client_ip = client_ip.as_str(); "Failed to handle {} {}: {}", method, path, e);
+                Response::builder()
+                    .version(version)
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Internal server error"))?
+            }
+        };
+        self.add_security_headers(response.headers_mut());
+        if let Some(origin) = allowed_origin {
+            add_cors_headers(response.headers_mut(), &origin);
+        }
+
+        let elapsed = started.elapsed();
+        self.metrics.record_request(&method, response.status(), elapsed);
+        let status = response.status().as_u16();
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        log::info!(target: ACCESS_LOG_TARGET,
+                   method = method.as_str(), path = path.as_str(), status = status,
+                   elapsed_ms = elapsed_ms, client_ip = client_ip.as_str();
+                   "{} {} {} {:.1}ms", method, path, status, elapsed_ms);
+        Ok(response)
+    }
+
+    // Every response gets these, so no page can be framed, sniffed or scripted unexpectedly
+    fn add_security_headers(&self, headers: &mut HeaderMap) {
+        headers.entry(header::CONTENT_SECURITY_POLICY).or_insert_with(|| self.content_security_policy.clone());
+        headers.entry(header::X_CONTENT_TYPE_OPTIONS).or_insert(HeaderValue::from_static("nosniff"));
+        headers.entry(header::REFERRER_POLICY).or_insert(HeaderValue::from_static("same-origin"));
+        if self.serves_https {
+            headers.entry(header::STRICT_TRANSPORT_SECURITY)
+                .or_insert(HeaderValue::from_static("max-age=31536000"));
+        }
+    }
+
+    async fn route_request(&self, request: Request<Body>, forwarded: ForwardedContext) -> Result<Response<Body>> {
         let (parts, body) = request.into_parts();
+        if self.shutting_down.load(Ordering::Acquire) {
+            // Connections kept alive may still send requests while the server drains
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::RETRY_AFTER, "10")
+                .header(header::CONTENT_TYPE, website::HTML_CONTENT_TYPE)
+                .body(Body::from(RESTARTING_PAGE))?);
+        }
+        if parts.headers.len() > self.max_header_count {
+            log::debug!("Rejected request with {} headers", parts.headers.len());
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+                .body(Body::from("Too many headers"))?);
+        }
+        let schema_ready = self.schema_ready.load(Ordering::Acquire);
+        if parts.uri.path() == "/readyz" {
+            // Orchestrators probe by address, so this skips the host check
+            let (status, message) = if schema_ready {
+                (StatusCode::OK, "ready")
+            } else {
+                (StatusCode::SERVICE_UNAVAILABLE, "migrating")
+            };
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(status)
+                .body(Body::from(message))?);
+        }
+        match parts.uri.path() {
+            // Like /readyz, for load balancers, which also probe by address
+            "/health" => return Self::health_check(parts.version, StatusCode::OK, "ok"),
+            // Scraped by address too, and kept apart from the site's own routes
+            "/metrics" if self.serves_metrics => return self.metrics.response(parts.version),
+            "/ready" => {
+                let database_up = schema_ready && {
+                    let deadline = Deadline::after(self.request_timeout);
+                    match deadline.run(self.database.ping()).await {
+                        Ok(Ok(())) => true,
+                        Ok(Err(e)) => {
+                            log::warn!("Database unavailable: {}", e);
+                            false
+                        },
+                        Err(_) => {
+                            log::warn!("Database did not answer before the deadline");
+                            false
+                        }
+                    }
+                };
+                return if database_up {
+                    Self::health_check(parts.version, StatusCode::OK, "ok")
+                } else {
+                    Self::health_check(parts.version, StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+                };
+            },
+            _ => {}
+        }
+        if !self.host_allowed(&forwarded) {
+            let client_ip = forwarded.client_ip.map(|ip| ip.to_string()).unwrap_or_default();
+            let host = forwarded.host.as_deref().unwrap_or_default();
+            log::debug!(client_ip = client_ip.as_str(), host = host;
+                "Rejected {} {} from {} for unexpected host {:?}", parts.method, parts.uri.path(), client_ip, host);
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Unexpected host"))?);
+        }
+        if !schema_ready {
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::from("starting up"))?);
+        }
+        let (event_slug, path) = event::split_event_path(parts.uri.path());
+        if event_slug.is_some() && path.is_empty() {
+            // Relative links on the event page resolve only beneath the trailing slash
+            return Ok(Response::builder()
+                .version(parts.version)
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(header::LOCATION, format!("{}/", parts.uri.path()))
+                .body(Body::empty())?);
+        }
+        let event = match event_slug {
+            None => None,
+            Some(slug) => match self.database.select_event(slug).await {
+                Ok(Some(event)) => Some(event),
+                Ok(None) => {
+                    log::debug!("No such event: {}", slug);
+                    return self.not_found(&parts);
+                },
+                Err(e) => {
+                    log::error!("Database error: {}", e);
+                    return Ok(Response::builder()
+                        .version(parts.version)
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Database error"))?);
+                }
+            }
+        };
+        let path = path.to_string();
         let method = AllowedMethod::find_from(&parts.method);
         match method {
             None => {
                 AllowedMethod::method_not_alllowed(parts.version)
             },
             Some(AllowedMethod::GET) | Some(AllowedMethod::HEAD) => {
-                self.yield_site(parts, body).await
+                Box::pin(self.yield_site(parts, body, &path, event.as_ref(), forwarded.client_ip)).await
             },
+            Some(AllowedMethod::OPTIONS) => self.preflight(&parts),
             Some(AllowedMethod::POST) => {
-                Ok(match self.website.validate_post_path(parts.uri) {
-                    None => {
+                Box::pin(self.handle_post(parts, body, &path, event, &forwarded)).await
+            }
+        }
+    }
+
+    // Split from route_request, and boxed there like yield_site. In debug builds, polling
+    // them all as one future took stack frames large enough to overflow a thread's stack.
+    async fn handle_post(&self,
+                         parts: request::Parts,
+                         body: Body,
+                         path: &str,
+                         event: Option<Event>,
+                         forwarded: &ForwardedContext) -> Result<Response<Body>> {
+        let post_path = self.website.validate_post_path(path);
+        if let (Some(_), Some(client_ip)) = (post_path, forwarded.client_ip) {
+            if let Err(retry_after) = self.rsvp_rate_limiter.try_request(client_ip, Instant::now()) {
+                let client_ip = client_ip.to_string();
+                log::debug!(client_ip = client_ip.as_str(); "Rate limited {}", client_ip);
+                return Self::too_many_requests(parts.version, retry_after);
+            }
+        }
+        Ok(match post_path {
+            None => {
+                log::debug!("Non-existent POST path: {}", parts.uri);
+                self.not_found(&parts)?
+            }
+            Some(_) if self.require_https_for_rsvp && !forwarded.https => {
+                Response::builder()
+                    .version(parts.version)
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("RSVPs must be sent over HTTPS"))?
+            }
+            Some(PostPath::EnterRsvp) => {
+                let deadline = Deadline::after(self.request_timeout);
+                let event_id = event.map(|event| event.id).unwrap_or(DEFAULT_EVENT_ID);
+                let response = self.enter_rsvp(parts.version, Formats::negotiate(&parts.headers), body, event_id, deadline);
+                match Self::within_deadline(parts.version, deadline, response).await {
+                    Err(e) => {
+                        log::warn!("Miscellaneous error: {}", e);
                         Response::builder()
                             .version(parts.version)
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::from("Non-existent POST path"))?
-                    }
-                    Some(PostPath::EnterRsvp) => {
-                        match self.enter_rsvp(parts.version, body).await {
-                            Err(e) => {
-                                log::warn!("Miscellaneous error: {}", e);
-                                Response::builder()
-                                    .version(parts.version)
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(Body::from("Miscellaneous error"))?
-                            },
-                            Ok(response) => response
-                        }
-                    }
-                })
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from("Miscellaneous error"))?
+                    },
+                    Ok(response) => response
+                }
             }
+            Some(PostPath::UpdateRsvp) => {
+                let deadline = Deadline::after(self.request_timeout);
+                let event_id = event.map(|event| event.id).unwrap_or(DEFAULT_EVENT_ID);
+                let response = self.update_rsvp(parts.version, Formats::negotiate(&parts.headers), body, event_id, deadline);
+                match Self::within_deadline(parts.version, deadline, response).await {
+                    Err(e) => {
+                        log::error!("Database error: {}", e);
+                        Response::builder()
+                            .version(parts.version)
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from("Database error"))?
+                    },
+                    Ok(response) => response
+                }
+            }
+            Some(PostPath::CancelRsvp) => {
+                let deadline = Deadline::after(self.request_timeout);
+                let event_id = event.map(|event| event.id).unwrap_or(DEFAULT_EVENT_ID);
+                let response = self.cancel_rsvp(parts.version, Formats::negotiate(&parts.headers), body, event_id, deadline);
+                match Self::within_deadline(parts.version, deadline, response).await {
+                    Err(e) => {
+                        log::error!("Database error: {}", e);
+                        Response::builder()
+                            .version(parts.version)
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from("Database error"))?
+                    },
+                    Ok(response) => response
+                }
+            }
+        })
+    }
+
+    // A browser asks before sending a cross-origin POST, and sends it only if this
+    // response allows its origin
+    fn preflight(&self, request_parts: &request::Parts) -> Result<Response<Body>> {
+        let response = Response::builder()
+            .version(request_parts.version)
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, CORS_ALLOWED_METHODS);
+        let origin = match request_parts.headers.get(header::ORIGIN) {
+            // Not a preflight, just a question of which methods are allowed
+            None => return Ok(response.body(Body::empty())?),
+            Some(origin) => origin
+        };
+        if self.allowed_origin(&request_parts.headers).is_none() {
+            log::debug!("Rejected preflight from origin {:?}", origin);
+            return Ok(Response::builder()
+                .version(request_parts.version)
+                .status(StatusCode::FORBIDDEN)
+                .header(header::VARY, "Origin")
+                .body(Body::from("Origin not allowed"))?);
+        }
+        Ok(response
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, CORS_ALLOWED_METHODS)
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+            .header(header::ACCESS_CONTROL_MAX_AGE, CORS_MAX_AGE_SECS.to_string())
+            .body(Body::empty())?)
+    }
+
+    // The request's Origin, if it is one allowed to call the API from another site
+    fn allowed_origin<'h>(&self, headers: &'h HeaderMap) -> Option<&'h HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        let origin_str = origin.to_str().ok()?;
+        self.cors_allowed_origins.iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin_str))
+            .then_some(origin)
+    }
+
+    fn health_check(version: version::Version, status: StatusCode, message: &str) -> Result<Response<Body>> {
+        Ok(Response::builder()
+            .version(version)
+            .status(status)
+            .header(header::CONTENT_TYPE, website::JSON_CONTENT_TYPE)
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::from(format!(r#"{{"status":"{}"}}"#, message)))?)
+    }
+
+    fn host_allowed(&self, forwarded: &ForwardedContext) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+        match &forwarded.host {
+            Some(host) => {
+                let host = without_port(host);
+                self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host))
+            },
+            None => false
+        }
+    }
+
+    async fn within_deadline<F>(version: version::Version,
+                                deadline: Deadline,
+                                response: F) -> Result<Response<Body>>
+        where F: Future<Output=Result<Response<Body>>> {
+
+        match deadline.run(response).await {
+            Err(_) => {
+                log::warn!("Request did not complete before its deadline");
+                Ok(Response::builder()
+                    .version(version)
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("Request timed out"))?)
+            },
+            Ok(response) => response
         }
     }
 
     async fn yield_site(&self,
                         request_parts: request::Parts,
-                        request_body: Body) -> Result<Response<Body>> {
+                        request_body: Body,
+                        path: &str,
+                        event: Option<&Event>,
+                        client_ip: Option<IpAddr>) -> Result<Response<Body>> {
         if !request_body.is_end_stream() {
+            if request_body.size_hint().lower() > self.max_body_bytes as u64 {
+                return Self::payload_too_large(request_parts.version);
+            }
             // Check if body is empty to conform to HTTP specification
             log::debug!("Received HTTP request with non-empty body: {:?}", &request_parts);
             return Ok(Response::builder()
@@ -121,68 +570,500 @@ impl App {
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from("A request must have an empty body"))?);
         }
+        if path == "/" {
+            let gzip = accepts_gzip(&request_parts.headers);
+            let (page, page_gzip) = match event {
+                Some(event) => {
+                    let page = self.website.event_page(event)?;
+                    let page_gzip = if gzip { Some(website::gzip(&page)?) } else { None };
+                    (page, page_gzip)
+                },
+                None => {
+                    let page_gzip = Some(self.website.main_page_gzip.clone()).filter(|_| gzip);
+                    (self.website.main_page.clone(), page_gzip)
+                }
+            };
+            return self.main_page(&request_parts, page, page_gzip);
+        }
+        if path == "/rsvp-status" {
+            let event_id = event.map(|event| event.id).unwrap_or(DEFAULT_EVENT_ID);
+            return self.rsvp_status(&request_parts, event_id, client_ip).await;
+        }
+        if let Some(asset) = self.website.asset(path) {
+            return self.static_asset(&request_parts, path, asset);
+        }
         let body = if &request_parts.method == &Method::HEAD {
             // HEAD requests yield empty bodies
             Body::empty()
+        } else if path == "/api/time" {
+            Body::from(self.server_time(OffsetDateTime::now_utc())?)
+        } else if path == "/api/schema" {
+            Body::from(self.to_json(&thebestofcmu_common::api_schema())?)
         } else {
-            match self.website.yield_site_body(request_parts.uri.clone()).await {
+            match self.website.yield_site_body(path).await {
                 Some(body) => body,
                 None => {
                     log::debug!("Not found: {}", request_parts.uri);
-                    let msg = "According to my book-keeping, that page does not exist.";
-                    return Ok(Response::builder()
-                        .version(request_parts.version)
-                        .status(StatusCode::NOT_FOUND)
-                        .body(Body::from(msg))?);
+                    return self.not_found(&request_parts);
                 }
             }
         };
-        Ok(Response::builder()
+        let content_type = if path == "/api/time" || path == "/api/schema" {
+            Some(website::JSON_CONTENT_TYPE)
+        } else {
+            self.website.content_type(path)
+        };
+        let mut response = Response::builder()
+            .version(request_parts.version)
+            .status(StatusCode::OK);
+        if let Some(content_type) = content_type {
+            response = response.header(header::CONTENT_TYPE, content_type);
+        }
+        Ok(response.body(body)?)
+    }
+
+    // HEAD gets the same headers as GET, so monitors can make conditional checks.
+    // The compressed page, if given, is served in place of the page.
+    fn main_page(&self,
+                 request_parts: &request::Parts,
+                 page: String,
+                 page_gzip: Option<Bytes>) -> Result<Response<Body>> {
+        let etag = website::page_etag(&page);
+        let etag = match &page_gzip {
+            // Each encoding is a distinct representation, with its own tag
+            Some(_) => format!("{}-gzip\"", etag.trim_end_matches('"')),
+            None => etag
+        };
+        let mut response = Response::builder()
+            .version(request_parts.version)
+            .header(header::ETAG, &etag)
+            .header(header::CACHE_CONTROL, "no-cache")
+            .header(header::VARY, "Accept-Encoding");
+
+        if if_none_match(&request_parts.headers, &etag) {
+            return Ok(response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())?);
+        }
+        let page = match page_gzip {
+            Some(page_gzip) => {
+                response = response.header(header::CONTENT_ENCODING, "gzip");
+                page_gzip
+            },
+            None => Bytes::from(page)
+        };
+        let content_length = page.len();
+        let body = if &request_parts.method == &Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(page)
+        };
+        Ok(response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, website::HTML_CONTENT_TYPE)
+            .header(header::CONTENT_LENGTH, content_length)
+            .body(body)?)
+    }
+
+    fn not_found(&self, request_parts: &request::Parts) -> Result<Response<Body>> {
+        let response = Response::builder()
             .version(request_parts.version)
+            .status(StatusCode::NOT_FOUND);
+        Ok(if accepts_html(&request_parts.headers) {
+            response
+                .header(header::CONTENT_TYPE, website::HTML_CONTENT_TYPE)
+                .body(Body::from(self.website.not_found_page()))?
+        } else {
+            response
+                .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(Body::from(website::NOT_FOUND_MESSAGE))?
+        })
+    }
+
+    // Embedded files change only with the binary, so caches may keep them for a while
+    fn static_asset(&self, request_parts: &request::Parts, path: &str, asset: &Asset) -> Result<Response<Body>> {
+        let mut response = Response::builder()
+            .version(request_parts.version)
+            .header(header::ETAG, &asset.etag)
+            .header(header::CACHE_CONTROL, format!("public, max-age={}", ASSET_MAX_AGE_SECS));
+        if if_none_match(&request_parts.headers, &asset.etag) {
+            return Ok(response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())?);
+        }
+        if let Some(content_type) = self.website.content_type(path) {
+            response = response.header(header::CONTENT_TYPE, content_type);
+        }
+        let body = if &request_parts.method == &Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(asset.bytes)
+        };
+        Ok(response
             .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, asset.bytes.len())
             .body(body)?)
     }
 
-    async fn enter_rsvp(&self, version: version::Version, body: Body) -> Result<Response<Body>> {
-        Ok(match ClientRSVP::decode(body).await {
+    async fn rsvp_status(&self,
+                         request_parts: &request::Parts,
+                         event_id: i32,
+                         client_ip: Option<IpAddr>) -> Result<Response<Body>> {
+        let version = request_parts.version;
+        // Limited like RSVPs, lest the names of invitees be guessed at speed
+        if let Some(client_ip) = client_ip {
+            if let Err(retry_after) = self.rsvp_rate_limiter.try_request(client_ip, Instant::now()) {
+                return Self::too_many_requests(version, retry_after);
+            }
+        }
+        let (first_name, invite_id) = match parse_status_query(request_parts.uri.query()) {
+            Ok(query) => query,
+            Err(message) => {
+                return Ok(Response::builder()
+                    .version(version)
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(message))?);
+            }
+        };
+        let deadline = Deadline::after(self.request_timeout);
+        let lookup = async {
+            let mut status = self.database.select_rsvp_by_name(event_id, &first_name, invite_id).await?;
+            // Anyone may know a first name, but the invite ID is given only to the invitee
+            if invite_id.is_none() {
+                status = status.without_details();
+            }
+            Ok(Response::builder()
+                .version(version)
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, website::JSON_CONTENT_TYPE)
+                .body(Body::from(self.to_json(&status)?))?)
+        };
+        Ok(match Self::within_deadline(version, deadline, lookup).await {
             Err(e) => {
-                log::warn!("Received bad client data: {}", e);
+                log::error!("Database error: {}", e);
                 Response::builder()
                     .version(version)
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from("Unable to parse RSVP json"))?
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Database error"))?
+            },
+            Ok(response) => response
+        })
+    }
+
+    // The server's clock, so clients can count down to the deadline accurately
+    fn server_time(&self, now: OffsetDateTime) -> Result<String> {
+        let deadline = match self.rsvp_deadline {
+            Some(deadline) => Some(deadline.format(&Rfc3339)?),
+            None => None
+        };
+        let (hours, minutes, _) = self.utc_offset.as_hms();
+        let timezone = format!("{}{:02}:{:02}",
+                               if self.utc_offset.is_negative() { '-' } else { '+' }, hours.abs(), minutes.abs());
+        self.to_json(&serde_json::json!({
+            "now": now.to_offset(self.utc_offset).format(&Rfc3339)?,
+            "timezone": timezone,
+            "deadline": deadline
+        }))
+    }
+
+    fn to_json<T>(&self, value: &T) -> Result<String>
+        where T: Serialize {
+
+        Ok(if self.pretty_json {
+            serde_json::to_string_pretty(value)?
+        } else {
+            serde_json::to_string(value)?
+        })
+    }
+
+    // Encodes the response to an API request in the negotiated format
+    fn api_response(&self, version: version::Version, formats: Formats, response: &ServerResponse) -> Result<Response<Body>> {
+        let body = match formats.response {
+            BodyFormat::Json => Body::from(self.to_json(response)?),
+            BodyFormat::Cbor => response.clone().encode_as(BodyFormat::Cbor)?
+        };
+        Ok(Response::builder()
+            .version(version)
+            .status(StatusCode::ACCEPTED)
+            .header(header::CONTENT_TYPE, formats.response.content_type())
+            .body(body)?)
+    }
+
+    // Yields the RSVP in the body, or else the response refusing it
+    async fn read_rsvp(&self,
+                       version: version::Version,
+                       format: BodyFormat,
+                       body: Body) -> Result<std::result::Result<ClientRSVP, Response<Body>>> {
+        let mut rsvp = match ClientRSVP::decode_as(body, self.max_body_bytes, format).await {
+            Err(e) => return Ok(Err(Self::undecodable_body(version, e, "RSVP")?)),
+            Ok(rsvp) => rsvp
+        };
+        if let Err(errors) = rsvp.validate_against(&RSVP_SCHEMA) {
+            let errors: Vec<String> = errors.iter().map(FieldError::to_string).collect();
+            return Ok(Err(Response::builder()
+                .version(version)
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(errors.join("\n")))?));
+        }
+        if !(1..=self.max_party_size).contains(&rsvp.details.guest_count) {
+            return Ok(Err(Response::builder()
+                .version(version)
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("guest_count must be between 1 and {}", self.max_party_size)))?));
+        }
+        // Validated above, so only blank numbers fail to normalize
+        rsvp.details.phone_number = rsvp.details.phone_number
+            .and_then(|phone_number| normalize_phone_number(&phone_number).ok());
+        Ok(Ok(rsvp))
+    }
+
+    async fn enter_rsvp(&self,
+                        version: version::Version,
+                        formats: Formats,
+                        body: Body,
+                        event_id: i32,
+                        deadline: Deadline) -> Result<Response<Body>> {
+        let rsvp = match self.read_rsvp(version, formats.request, body).await? {
+            Ok(rsvp) => rsvp,
+            Err(refusal) => {
+                self.metrics.record_rsvp_rejected();
+                return Ok(refusal);
             }
-            Ok(rsvp) => {
-                match self.database.insert_rsvp(rsvp).await {
-                    Err(e) => {
-                        log::error!("Database error: {}", e);
-                        Response::builder()
-                            .version(version)
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Body::from("Database error"))?
-                    },
-                    Ok(response) => {
-                        Response::builder()
-                            .version(version)
-                            .status(StatusCode::ACCEPTED)
-                            .body(Body::from(serde_json::to_string(&response)?))?
-                    }
+        };
+        Ok(match self.database.insert_rsvp(rsvp.clone(), event_id, deadline).await {
+            Err(e) => match &self.rsvp_queue {
+                Some(rsvp_queue) if is_connection_error(&e) => {
+                    log::warn!("Database unreachable, queueing RSVP: {}", e);
+                    let submitted_at = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)?
+                        .as_secs();
+                    rsvp_queue.enqueue(&QueuedRsvp { rsvp, event_id, submitted_at }).await?;
+                    self.metrics.record_rsvp_accepted();
+                    self.api_response(version, formats, &ServerResponse::Queued)?
+                },
+                _ => {
+                    log::error!("Database error: {}", e);
+                    Response::builder()
+                        .version(version)
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Database error"))?
                 }
+            },
+            Ok(response) => {
+                if response == ServerResponse::Success {
+                    self.metrics.record_rsvp_accepted();
+                } else {
+                    self.metrics.record_rsvp_rejected();
+                }
+                self.api_response(version, formats, &response)?
             }
         })
     }
 
+    async fn update_rsvp(&self,
+                         version: version::Version,
+                         formats: Formats,
+                         body: Body,
+                         event_id: i32,
+                         deadline: Deadline) -> Result<Response<Body>> {
+        let rsvp = match self.read_rsvp(version, formats.request, body).await? {
+            Ok(rsvp) => rsvp,
+            Err(refusal) => return Ok(refusal)
+        };
+        // A first name is no secret, so changing someone's RSVP takes their invite ID too
+        if rsvp.invite_id.is_none() {
+            return self.api_response(version, formats, &ServerResponse::InviteIdRequired);
+        }
+        let response = self.database.update_rsvp(rsvp, event_id, deadline).await?;
+        self.api_response(version, formats, &response)
+    }
+
+    async fn cancel_rsvp(&self,
+                         version: version::Version,
+                         formats: Formats,
+                         body: Body,
+                         event_id: i32,
+                         deadline: Deadline) -> Result<Response<Body>> {
+        let decoded = ClientCancellation::decode_as(body, self.max_body_bytes, formats.request).await;
+        let cancellation = match decoded {
+            Err(e) => return Self::undecodable_body(version, e, "cancellation"),
+            Ok(cancellation) => cancellation
+        };
+        // A first name is no secret, so cancelling someone's RSVP takes their invite ID too
+        if cancellation.invite_id.is_none() {
+            return self.api_response(version, formats, &ServerResponse::InviteIdRequired);
+        }
+        let response = self.database.delete_rsvp(cancellation, event_id, deadline).await?;
+        self.api_response(version, formats, &response)
+    }
+
+    fn too_many_requests(version: version::Version, retry_after: Duration) -> Result<Response<Body>> {
+        // Rounded up, so the client does not retry a moment too soon
+        let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Ok(Response::builder()
+            .version(version)
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(header::RETRY_AFTER, retry_after_secs)
+            .body(Body::from("Too many requests. Please try again later"))?)
+    }
+
+    fn payload_too_large(version: version::Version) -> Result<Response<Body>> {
+        Ok(Response::builder()
+            .version(version)
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(Body::from("Request body is too large"))?)
+    }
+
+    // Refuses a body which could not be decoded as the named kind of request. A body which
+    // could not be read at all fails the request instead.
+    fn undecodable_body(version: version::Version, error: DecodeError, kind: &str) -> Result<Response<Body>> {
+        let message = match error {
+            DecodeError::Read(e) => return Err(e.into()),
+            DecodeError::TooLarge { .. } => return Self::payload_too_large(version),
+            DecodeError::Empty => String::from("Request body is required"),
+            DecodeError::InvalidUtf8(ref e) => {
+                log::warn!("Received bad client data: {}", e);
+                String::from("Request body must be UTF-8")
+            },
+            DecodeError::InvalidJson(ref e) => {
+                log::warn!("Received bad client data: {}", e);
+                format!("Unable to parse {} json", kind)
+            },
+            DecodeError::InvalidCbor(ref e) => {
+                log::warn!("Received bad client data: {}", e);
+                format!("Unable to parse {} cbor", kind)
+            }
+        };
+        Ok(Response::builder()
+            .version(version)
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(message))?)
+    }
+
 
 }
 
-mod compat {
+/// The formats of an API request's body and of the response to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Formats {
+    request: BodyFormat,
+    response: BodyFormat
+}
+
+impl Formats {
+    // The request body is JSON unless labelled otherwise. The response is in the format
+    // the client accepts, or else that of the request.
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let header_format = |name, parse: fn(&str) -> Option<BodyFormat>| {
+            headers.get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse)
+        };
+        let request = header_format(header::CONTENT_TYPE, BodyFormat::from_content_type).unwrap_or(BodyFormat::Json);
+        let response = header_format(header::ACCEPT, BodyFormat::from_accept).unwrap_or(request);
+        Self { request, response }
+    }
+}
+
+fn add_cors_headers(headers: &mut HeaderMap, origin: &HeaderValue) {
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    // Caches must not hand one origin's response to another
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+}
+
+// Also yields a receiver which hears once the shutdown future completes
+fn notify_shutdown<F>(shutdown_future: F) -> (impl Future<Output=()>, Receiver<()>)
+    where F: Future<Output=()> {
+
+    let (began, shutdown_began) = channel::bounded(1);
+    let shutdown_future = async move {
+        shutdown_future.await;
+        let _ = began.try_send(());
+    };
+    (shutdown_future, shutdown_began)
+}
+
+// Whether the client already has the representation with the entity tag
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == etag || tag.trim() == "*")
+}
+
+// Whether the Accept header, if any, admits HTML
+fn accepts_html(headers: &HeaderMap) -> bool {
+    let mut accept = headers.get_all(header::ACCEPT).iter().peekable();
+    if accept.peek().is_none() {
+        return true;
+    }
+    accept
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| range.split(';').next().unwrap_or_default().trim())
+        .any(|range| ["text/html", "text/*", "*/*"].iter().any(|html| range.eq_ignore_ascii_case(html)))
+}
+
+// Whether the Accept-Encoding header admits gzip, by name or by wildcard, with a nonzero weight
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let refused = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .any(|weight| weight.trim().parse::<f32>().map(|weight| weight == 0.0).unwrap_or(false));
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+fn is_connection_error(error: &eyre::Report) -> bool {
+    error.downcast_ref::<sqlx::Error>()
+        .map(database::is_connection_error)
+        .unwrap_or(false)
+}
+
+// Reads the first name, and any invite ID, which /rsvp-status looks up
+fn parse_status_query(query: Option<&str>) -> std::result::Result<(String, Option<InviteId>), String> {
+    let mut first_name = None;
+    let mut invite_id = None;
+    for (key, value) in form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+        match key.as_ref() {
+            "first_name" => first_name = Some(value.trim().to_string()),
+            "invite_id" => invite_id = Some(value.parse::<InviteId>().map_err(|e| e.to_string())?),
+            _ => {}
+        }
+    }
+    match first_name {
+        Some(first_name) if !first_name.is_empty() => Ok((first_name, invite_id)),
+        _ => Err(String::from("first_name is required"))
+    }
+}
+
+fn without_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal
+        return host.find(']').map(|end| &host[..=end]).unwrap_or(host);
+    }
+    host.rsplit_once(':').map(|(name, _)| name).unwrap_or(host)
+}
+
+pub mod compat {
+    use std::net::IpAddr;
     use std::pin::Pin;
     use std::task::{Context, Poll};
     use async_std::io;
     use async_std::net::{self, TcpListener, TcpStream};
+    #[cfg(unix)]
+    use async_std::os::unix::net as unix;
     use async_std::prelude::*;
     use async_std::task;
     use hyper::server::accept::Accept;
+    use crate::connection_limit::{ConnectionTracker, OpenConnection};
 
     #[derive(Clone)]
     pub struct HyperExecutor;
@@ -197,61 +1078,136 @@ mod compat {
         }
     }
 
-    pub struct HyperListener<'listener> {
-        incoming: net::Incoming<'listener>,
+    pub struct HyperListener<'listener> {
+        incoming: net::Incoming<'listener>,
+        connection_tracker: ConnectionTracker,
+    }
+
+    impl<'listener> HyperListener<'listener> {
+        pub fn new(listener: &'listener TcpListener, connection_tracker: ConnectionTracker) -> Self {
+            Self {
+                incoming: listener.incoming(),
+                connection_tracker,
+            }
+        }
+    }
+
+    impl Accept for HyperListener<'_> {
+        type Conn = HyperStream;
+        type Error = io::Error;
+
+        fn poll_accept(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+        ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+            loop {
+                let stream = task::ready!(Pin::new(&mut self.incoming).poll_next(cx)).unwrap()?;
+                let ip = match stream.peer_addr() {
+                    Ok(address) => address.ip(),
+                    Err(e) => {
+                        // The peer may have reset the connection already. That is no reason to
+                        // stop accepting others, so the stream is dropped like a refused one.
+                        log::warn!("Dropped connection whose peer address is unknown: {}", e);
+                        continue;
+                    }
+                };
+                match self.connection_tracker.try_open(ip) {
+                    Some(connection) => {
+                        return Poll::Ready(Some(Ok(HyperStream { stream, peer_ip: Some(ip), _connection: Some(connection) })));
+                    }
+                    None => {
+                        // Dropping the stream closes it
+                        log::warn!("Refused connection from {}: too many open connections", ip);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub struct UnixHyperListener<'listener> {
+        incoming: unix::Incoming<'listener>,
     }
 
-    impl<'listener> HyperListener<'listener> {
-        pub fn new(listener: &'listener TcpListener) -> Self {
+    #[cfg(unix)]
+    impl<'listener> UnixHyperListener<'listener> {
+        pub fn new(listener: &'listener unix::UnixListener) -> Self {
             Self {
                 incoming: listener.incoming(),
             }
         }
     }
 
-    impl Accept for HyperListener<'_> {
-        type Conn = HyperStream;
+    #[cfg(unix)]
+    impl Accept for UnixHyperListener<'_> {
+        type Conn = HyperStream<unix::UnixStream>;
         type Error = io::Error;
 
         fn poll_accept(
             mut self: Pin<&mut Self>,
             cx: &mut Context,
         ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+            // Peers on a Unix socket have no IP to limit connections by
             let stream = task::ready!(Pin::new(&mut self.incoming).poll_next(cx)).unwrap()?;
-            Poll::Ready(Some(Ok(HyperStream(stream))))
+            Poll::Ready(Some(Ok(HyperStream::unlimited(stream))))
+        }
+    }
+
+    /// Connections which may know the IP address of their peer
+    pub trait Peer {
+        fn peer_ip(&self) -> Option<IpAddr>;
+    }
+
+    pub struct HyperStream<S = TcpStream> {
+        stream: S,
+        peer_ip: Option<IpAddr>,
+        // Counts as open until the stream is dropped
+        _connection: Option<OpenConnection>,
+    }
+
+    impl<S> HyperStream<S> {
+        /// Wraps a stream which does not count towards any connection limit
+        pub fn unlimited(stream: S) -> Self {
+            Self { stream, peer_ip: None, _connection: None }
         }
     }
 
-    pub struct HyperStream(TcpStream);
+    impl<S> Peer for HyperStream<S> {
+        fn peer_ip(&self) -> Option<IpAddr> {
+            self.peer_ip
+        }
+    }
 
-    impl tokio::io::AsyncRead for HyperStream {
+    impl<S> tokio::io::AsyncRead for HyperStream<S>
+        where S: io::Read + Unpin {
         fn poll_read(
             mut self: Pin<&mut Self>,
             cx: &mut Context,
             buf: &mut tokio::io::ReadBuf<'_>,
         ) -> Poll<io::Result<()>> {
             let bytes =
-                task::ready!(Pin::new(&mut self.0).poll_read(cx, buf.initialize_unfilled())?);
+                task::ready!(Pin::new(&mut self.stream).poll_read(cx, buf.initialize_unfilled())?);
             buf.advance(bytes);
             Poll::Ready(Ok(()))
         }
     }
 
-    impl tokio::io::AsyncWrite for HyperStream {
+    impl<S> tokio::io::AsyncWrite for HyperStream<S>
+        where S: io::Write + Unpin {
         fn poll_write(
             mut self: Pin<&mut Self>,
             cx: &mut Context,
             buf: &[u8],
         ) -> Poll<io::Result<usize>> {
-            Pin::new(&mut self.0).poll_write(cx, buf)
+            Pin::new(&mut self.stream).poll_write(cx, buf)
         }
 
         fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-            Pin::new(&mut self.0).poll_flush(cx)
+            Pin::new(&mut self.stream).poll_flush(cx)
         }
 
         fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-            Pin::new(&mut self.0).poll_close(cx)
+            Pin::new(&mut self.stream).poll_close(cx)
         }
     }
 }
@@ -266,7 +1222,8 @@ mod tls {
     use hyper::server::accept::Accept;
     use rustls::ServerConfig;
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-    use crate::app::compat::{HyperListener, HyperStream};
+    use std::net::IpAddr;
+    use crate::app::compat::{HyperListener, HyperStream, Peer};
 
     enum State {
         Handshaking(tokio_rustls::Accept<HyperStream>),
@@ -278,17 +1235,26 @@ mod tls {
     // TlsStream implements AsyncRead/AsyncWrite handshaking tokio_rustls::Accept first
     pub struct TlsStream {
         state: State,
+        peer_ip: Option<IpAddr>,
     }
 
     impl TlsStream {
         fn new(stream: HyperStream, config: Arc<ServerConfig>) -> TlsStream {
+            let peer_ip = stream.peer_ip();
             let accept = tokio_rustls::TlsAcceptor::from(config).accept(stream);
             TlsStream {
                 state: State::Handshaking(accept),
+                peer_ip,
             }
         }
     }
 
+    impl Peer for TlsStream {
+        fn peer_ip(&self) -> Option<IpAddr> {
+            self.peer_ip
+        }
+    }
+
     impl AsyncRead for TlsStream {
         fn poll_read(
             self: Pin<&mut Self>,
@@ -372,4 +1338,1140 @@ mod tls {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+    use crate::captured_log;
+    use crate::database::PoolSettings;
+    use crate::metrics;
+    use thebestofcmu_common::RsvpStatus;
+
+    fn test_app() -> Result<App> {
+        Ok(App {
+            database: Database::connect_lazy("postgres://localhost/thebestofcmu", None, true, PoolSettings::default())?,
+            website: Website {
+                favicon: Asset::new(&[0, 0, 1, 0]),
+                kayaking_image: Asset::new(b"RIFF"),
+                main_page: String::from("<!DOCTYPE html><p>Kayaking</p>"),
+                main_page_gzip: website::gzip("<!DOCTYPE html><p>Kayaking</p>")?,
+                minify_html: true,
+                event_details: Default::default(),
+                footer_html: String::new()
+            },
+            request_timeout: Duration::from_secs(30),
+            max_connections_per_ip: 64,
+            max_header_count: 100,
+            max_header_bytes: 64 * 1024,
+            max_body_bytes: 64 * 1024,
+            allowed_hosts: Vec::new(),
+            rsvp_queue: None,
+            utc_offset: UtcOffset::UTC,
+            rsvp_deadline: None,
+            require_https_for_rsvp: false,
+            trusted_proxies: Vec::new(),
+            pretty_json: false,
+            max_party_size: 4,
+            rsvp_rate_limiter: RateLimiter::new(0, Duration::from_secs(60)),
+            cors_allowed_origins: Vec::new(),
+            content_security_policy: HeaderValue::from_static("default-src 'self'"),
+            metrics: Arc::new(Metrics::default()),
+            serves_metrics: true,
+            serves_https: false,
+            schema_ready: Arc::new(AtomicBool::new(true)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            shutdown_timeout: Duration::from_secs(30)
+        })
+    }
+
+    async fn body_string(response: Response<Body>) -> Result<String> {
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+
+    struct SetOnDrop<'f>(&'f AtomicBool);
+
+    impl Drop for SetOnDrop<'_> {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[async_std::test]
+    async fn slow_query_cancelled_at_deadline() -> Result<()> {
+        let cancelled = AtomicBool::new(false);
+        let slow_query = async {
+            let _guard = SetOnDrop(&cancelled);
+            task::sleep(Duration::from_secs(10)).await;
+            Ok(Response::new(Body::empty()))
+        };
+        let deadline = Deadline::after(Duration::from_millis(50));
+        let response = App::within_deadline(version::Version::HTTP_11, deadline, slow_query).await?;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert!(cancelled.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn fast_query_within_deadline() -> Result<()> {
+        let deadline = Deadline::after(Duration::from_secs(10));
+        let response = App::within_deadline(
+            version::Version::HTTP_11, deadline, async { Ok(Response::new(Body::empty())) }
+        ).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn empty_rsvp_body() -> Result<()> {
+        let request = Request::post("/enter-rsvp").body(Body::empty())?;
+        let response = test_app()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("Request body is required", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn update_rsvp_validated_like_new_rsvps() -> Result<()> {
+        let request = Request::post("/update-rsvp")
+            .body(Body::from(r#"{"first_name":"","details":{"phone_number":null,"email_address":"alice@example.com"}}"#))?;
+        let response = test_app()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("first_name is required", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn changing_rsvp_needs_invite_id() -> Result<()> {
+        let mut app = test_app()?;
+        app.database = Database::connect_lazy("sqlite::memory:", None, true, PoolSettings::default())?;
+        app.database.migrate().await?;
+        app.database.insert_invite(DEFAULT_EVENT_ID, "Alice", None, None).await?;
+        let rsvp = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "alice@example.com"}}"#;
+        app.handle_request(Request::post("/enter-rsvp").body(Body::from(rsvp))?).await?;
+        let update = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "mallory@example.com"}}"#;
+        let cancellation = r#"{"first_name": "Alice"}"#;
+
+        for (path, body) in [("/update-rsvp", update), ("/cancel-rsvp", cancellation)] {
+            let response = app.handle_request(Request::post(path).body(Body::from(body))?).await?;
+            let response: ServerResponse = serde_json::from_str(&body_string(response).await?)?;
+            assert_eq!(ServerResponse::InviteIdRequired, response, "{}", path);
+        }
+        match app.database.select_rsvp_by_name(DEFAULT_EVENT_ID, "Alice", None).await? {
+            RsvpStatus::RSVPed(details, _) => assert_eq!(Some("alice@example.com"), details.email_address.as_deref()),
+            other => panic!("{:?}", other)
+        }
+
+        let invite_id = app.database.select_invites(DEFAULT_EVENT_ID).await?[0].id;
+        let cancellation = format!(r#"{{"first_name": "Alice", "invite_id": {}}}"#, invite_id);
+        let response = app.handle_request(Request::post("/cancel-rsvp").body(Body::from(cancellation))?).await?;
+        let response: ServerResponse = serde_json::from_str(&body_string(response).await?)?;
+        assert_eq!(ServerResponse::Success, response);
+        Ok(())
+    }
+
+    fn app_for_hosts() -> Result<App> {
+        let mut app = test_app()?;
+        app.allowed_hosts = vec![String::from("thebestofcmu.example"), String::from("[::1]")];
+        Ok(app)
+    }
+
+    #[async_std::test]
+    async fn allowed_host() -> Result<()> {
+        let app = app_for_hosts()?;
+        for host in ["thebestofcmu.example", "TheBestOfCMU.example:8443", "[::1]:8080"] {
+            let request = Request::get("/").header(header::HOST, host).body(Body::empty())?;
+            let response = app.handle_request(request).await?;
+            assert_eq!(StatusCode::OK, response.status(), "{}", host);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn disallowed_host() -> Result<()> {
+        let request = Request::get("/").header(header::HOST, "attacker.example").body(Body::empty())?;
+        let response = app_for_hosts()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn forwarded_host() -> Result<()> {
+        let mut app = app_for_hosts()?;
+        let request = || Request::get("/")
+            .header(header::HOST, "internal:8080")
+            .header("X-Forwarded-Host", "thebestofcmu.example")
+            .extension(PeerAddress(IpAddr::from(PROXY)))
+            .body(Body::empty());
+        assert_eq!(StatusCode::BAD_REQUEST, app.handle_request(request()?).await?.status());
+
+        app.trusted_proxies = vec![IpAddr::from(PROXY)];
+        assert_eq!(StatusCode::OK, app.handle_request(request()?).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn missing_host() -> Result<()> {
+        let request = Request::get("/").body(Body::empty())?;
+        let response = app_for_hosts()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+        // Without an allowlist, anything goes
+        let request = Request::get("/").body(Body::empty())?;
+        let response = test_app()?.handle_request(request).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn migration_in_progress() -> Result<()> {
+        let app = test_app()?;
+        app.schema_ready.store(false, Ordering::Release);
+
+        let response = app.handle_request(Request::get("/readyz").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!("migrating", body_string(response).await?);
+
+        let response = app.handle_request(Request::get("/").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!("starting up", body_string(response).await?);
+
+        app.schema_ready.store(true, Ordering::Release);
+        let response = app.handle_request(Request::get("/readyz").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("ready", body_string(response).await?);
+
+        let response = app.handle_request(Request::get("/").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn connect_and_trace_not_allowed() -> Result<()> {
+        let app = test_app()?;
+        let requests = [
+            Request::builder().method(Method::CONNECT).uri("thebestofcmu.example:443").body(Body::empty())?,
+            Request::builder().method(Method::TRACE).uri("/").body(Body::empty())?
+        ];
+        for request in requests {
+            let method = request.method().clone();
+            let response = app.handle_request(request).await?;
+            assert_eq!(StatusCode::METHOD_NOT_ALLOWED, response.status(), "{}", method);
+            let allowed: Vec<&str> = response.headers()
+                .get_all(header::ALLOW)
+                .iter()
+                .map(|value| value.to_str().unwrap())
+                .collect();
+            assert_eq!(vec!["GET", "HEAD", "POST", "OPTIONS"], allowed, "{}", method);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn builder_with_header_limits() -> Result<()> {
+        let mut app = test_app()?;
+        app.max_header_count = 20;
+        app.max_header_bytes = MIN_HEADER_BYTES;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listener = compat::HyperListener::new(&listener, ConnectionTracker::new(1, Vec::new()));
+        let _builder = app.with_header_limits(Server::builder(listener));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn proxy_connections_not_capped() -> Result<()> {
+        use std::pin::Pin;
+        use async_std::net::TcpStream;
+        use hyper::server::accept::Accept;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let tracker = ConnectionTracker::new(1, vec![IpAddr::from([127, 0, 0, 1])]);
+        let mut listener = compat::HyperListener::new(&listener, tracker);
+        // The proxy holds three connections at once, past the cap of one
+        let mut accepted = Vec::new();
+        for _ in 0..3 {
+            let client = TcpStream::connect(address).await?;
+            let connection = future::poll_fn(|cx| Pin::new(&mut listener).poll_accept(cx))
+                .timeout(Duration::from_secs(5)).await?;
+            accepted.push((client, connection.ok_or_else(|| eyre::eyre!("Listener closed"))??));
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn too_many_headers() -> Result<()> {
+        let mut app = test_app()?;
+        app.max_header_count = 2;
+        let request = Request::get("/")
+            .header("X-First", "1")
+            .header("X-Second", "2")
+            .body(Body::empty())?;
+        assert_eq!(StatusCode::OK, app.handle_request(request).await?.status());
+
+        let request = Request::get("/")
+            .header("X-First", "1")
+            .header("X-Second", "2")
+            .header("X-Third", "3")
+            .body(Body::empty())?;
+        assert_eq!(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, app.handle_request(request).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn header_bytes_below_minimum() -> Result<()> {
+        let mut app = test_app()?;
+        app.max_header_bytes = MIN_HEADER_BYTES - 1;
+        let error = app.start_server("127.0.0.1:0".parse()?, None, async {}).await.unwrap_err();
+        assert!(error.to_string().contains("max_header_bytes"), "{}", error);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn serve_over_unix_socket() -> Result<()> {
+        use async_std::channel;
+        use async_std::io::{ReadExt, WriteExt};
+        use async_std::os::unix::net::UnixStream;
+
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("thebestofcmu.sock");
+        let (shutdown, shutdown_received) = channel::bounded::<()>(1);
+        let server = {
+            let path = path.clone();
+            task::spawn(async move {
+                let shutdown_future = async move { let _ = shutdown_received.recv().await; };
+                test_app()?.start_unix_server(path.as_path().into(), shutdown_future).await
+            })
+        };
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => task::sleep(Duration::from_millis(10)).await
+            }
+        };
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        shutdown.send(()).await?;
+        server.await?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn stuck_request_forced_at_shutdown_timeout() -> Result<()> {
+        use async_std::channel;
+        use async_std::io::WriteExt;
+        use async_std::os::unix::net::UnixStream;
+
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("thebestofcmu.sock");
+        let (shutdown, shutdown_received) = channel::bounded::<()>(1);
+        let server = {
+            let path = path.clone();
+            task::spawn(async move {
+                let mut app = test_app()?;
+                app.shutdown_timeout = Duration::from_millis(100);
+                let shutdown_future = async move { let _ = shutdown_received.recv().await; };
+                app.start_unix_server(path.as_path().into(), shutdown_future).await
+            })
+        };
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => task::sleep(Duration::from_millis(10)).await
+            }
+        };
+        // The request never finishes, since its headers never end
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").await?;
+        task::sleep(Duration::from_millis(50)).await;
+
+        shutdown.send(()).await?;
+        server.timeout(Duration::from_secs(5)).await??;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn server_time_fields() -> Result<()> {
+        let mut app = test_app()?;
+        app.utc_offset = UtcOffset::from_hms(-4, 0, 0)?;
+        let now = OffsetDateTime::from_unix_timestamp(1_662_089_400)?;
+
+        let time: serde_json::Value = serde_json::from_str(&app.server_time(now)?)?;
+        assert_eq!("2022-09-01T23:30:00-04:00", time["now"]);
+        assert_eq!("-04:00", time["timezone"]);
+        assert!(time["deadline"].is_null());
+
+        app.rsvp_deadline = Some(OffsetDateTime::parse("2022-09-10T23:59:00-04:00", &Rfc3339)?);
+        let time: serde_json::Value = serde_json::from_str(&app.server_time(now)?)?;
+        let deadline = OffsetDateTime::parse(time["deadline"].as_str().unwrap(), &Rfc3339)?;
+        assert_eq!(app.rsvp_deadline, Some(deadline));
+
+        let response = app.handle_request(Request::get("/api/time").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        let time: serde_json::Value = serde_json::from_str(&body_string(response).await?)?;
+        OffsetDateTime::parse(time["now"].as_str().unwrap(), &Rfc3339)?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn head_root_matches_get() -> Result<()> {
+        let app = test_app()?;
+        let get = app.handle_request(Request::get("/").body(Body::empty())?).await?;
+        let head = app.handle_request(Request::head("/").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, head.status());
+        assert_eq!(get.headers(), head.headers());
+        for name in [header::ETAG, header::CONTENT_TYPE, header::CONTENT_LENGTH, header::CACHE_CONTROL] {
+            assert!(head.headers().contains_key(&name), "{}", name);
+        }
+        assert_eq!("", body_string(head).await?);
+        assert_eq!(app.website.main_page, body_string(get).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn head_root_not_modified() -> Result<()> {
+        let app = test_app()?;
+        let etag = website::page_etag(&app.website.main_page);
+        let request = Request::head("/").header(header::IF_NONE_MATCH, &etag).body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(etag, response.headers()[header::ETAG]);
+
+        let request = Request::head("/").header(header::IF_NONE_MATCH, "\"stale\"").body(Body::empty())?;
+        assert_eq!(StatusCode::OK, app.handle_request(request).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn gzip_when_accepted() -> Result<()> {
+        use std::io::Read;
+        let app = test_app()?;
+        let request = Request::get("/").header(header::ACCEPT_ENCODING, "br;q=1.0, gzip;q=0.8").body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("gzip", response.headers()[header::CONTENT_ENCODING]);
+        assert_eq!("Accept-Encoding", response.headers()[header::VARY]);
+        assert_ne!(website::page_etag(&app.website.main_page), response.headers()[header::ETAG]);
+
+        let compressed = hyper::body::to_bytes(response.into_body()).await?;
+        let mut page = String::new();
+        flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut page)?;
+        assert_eq!(app.website.main_page, page);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn plain_unless_gzip_accepted() -> Result<()> {
+        let app = test_app()?;
+        for accept_encoding in [None, Some("identity"), Some("br"), Some("gzip;q=0"), Some("deflate, gzip; q=0.000")] {
+            let mut request = Request::get("/");
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            let response = app.handle_request(request.body(Body::empty())?).await?;
+            assert!(!response.headers().contains_key(header::CONTENT_ENCODING), "{:?}", accept_encoding);
+            assert_eq!(app.website.main_page, body_string(response).await?);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn assets_never_compressed() -> Result<()> {
+        let app = test_app()?;
+        let request = Request::get("/favicon.ico").header(header::ACCEPT_ENCODING, "gzip").body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn asset_with_etag() -> Result<()> {
+        let app = test_app()?;
+        let response = app.handle_request(Request::get("/favicon.ico").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(app.website.favicon.etag, response.headers()[header::ETAG]);
+        assert_eq!("public, max-age=86400", response.headers()[header::CACHE_CONTROL]);
+        assert_eq!("image/x-icon", response.headers()[header::CONTENT_TYPE]);
+        assert_eq!(vec![0, 0, 1, 0], hyper::body::to_bytes(response.into_body()).await?.to_vec());
+
+        // Assets with different contents have different tags
+        assert_ne!(app.website.favicon.etag, app.website.kayaking_image.etag);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn asset_not_modified() -> Result<()> {
+        let app = test_app()?;
+        let etag = app.website.kayaking_image.etag.clone();
+        let request = Request::get("/kayaking-background.webp")
+            .header(header::IF_NONE_MATCH, format!("\"stale\", {}", etag))
+            .body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(etag, response.headers()[header::ETAG]);
+        assert_eq!("", body_string(response).await?);
+
+        let request = Request::get("/kayaking-background.webp")
+            .header(header::IF_NONE_MATCH, "\"stale\"")
+            .body(Body::empty())?;
+        assert_eq!(StatusCode::OK, app.handle_request(request).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn not_found_as_html() -> Result<()> {
+        let app = test_app()?;
+        let requests = [
+            Request::get("/missing.png").header(header::ACCEPT, "text/html,application/xhtml+xml;q=0.9").body(Body::empty())?,
+            Request::get("/missing.png").body(Body::empty())?,
+            Request::post("/missing").header(header::ACCEPT, "*/*").body(Body::empty())?
+        ];
+        for request in requests {
+            let response = app.handle_request(request).await?;
+            assert_eq!(StatusCode::NOT_FOUND, response.status());
+            assert_eq!("text/html; charset=utf-8", response.headers()[header::CONTENT_TYPE]);
+            assert!(body_string(response).await?.contains("<title>Not found</title>"));
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn not_found_as_plain_text() -> Result<()> {
+        let app = test_app()?;
+        let request = Request::get("/missing.png").header(header::ACCEPT, "application/json").body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("text/plain; charset=utf-8", response.headers()[header::CONTENT_TYPE]);
+        assert_eq!(website::NOT_FOUND_MESSAGE, body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn security_headers() -> Result<()> {
+        let mut app = test_app()?;
+        for path in ["/", "/favicon.ico", "/api/time", "/missing.png"] {
+            let response = app.handle_request(Request::get(path).body(Body::empty())?).await?;
+            let headers = response.headers();
+            assert_eq!("default-src 'self'", headers[header::CONTENT_SECURITY_POLICY], "{}", path);
+            assert_eq!("nosniff", headers[header::X_CONTENT_TYPE_OPTIONS], "{}", path);
+            assert_eq!("same-origin", headers[header::REFERRER_POLICY], "{}", path);
+            assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY), "{}", path);
+        }
+        app.serves_https = true;
+        let response = app.handle_request(Request::get("/").body(Body::empty())?).await?;
+        assert_eq!("max-age=31536000", response.headers()[header::STRICT_TRANSPORT_SECURITY]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn health_without_database() -> Result<()> {
+        let mut app = test_app()?;
+        app.schema_ready.store(false, Ordering::Release);
+        let response = app.handle_request(Request::get("/health").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("application/json", response.headers()[header::CONTENT_TYPE]);
+        assert_eq!(r#"{"status":"ok"}"#, body_string(response).await?);
+
+        app.allowed_hosts = vec![String::from("kayaking.example.com")];
+        let response = app.handle_request(Request::get("/health").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn not_ready_without_database() -> Result<()> {
+        let mut app = test_app()?;
+        app.database = Database::connect_lazy("postgres://localhost:1/thebestofcmu", None, true, PoolSettings::default())?;
+        app.request_timeout = Duration::from_millis(500);
+        let response = app.handle_request(Request::get("/ready").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        assert_eq!(r#"{"status":"unavailable"}"#, body_string(response).await?);
+        Ok(())
+    }
+
+    // Run with TEST_DATABASE_URL pointing to any database
+    #[async_std::test]
+    #[ignore = "needs a database at TEST_DATABASE_URL"]
+    async fn ready_with_database() -> Result<()> {
+        let mut app = test_app()?;
+        app.database = Database::connect_lazy(&std::env::var("TEST_DATABASE_URL")?, None, true, PoolSettings::default())?;
+        let response = app.handle_request(Request::get("/ready").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(r#"{"status":"ok"}"#, body_string(response).await?);
+
+        app.schema_ready.store(false, Ordering::Release);
+        let response = app.handle_request(Request::get("/ready").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn rsvps_rate_limited() -> Result<()> {
+        let mut app = test_app()?;
+        app.rsvp_rate_limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let rsvp_from = |ip: [u8; 4]| {
+            Request::post("/enter-rsvp").extension(PeerAddress(IpAddr::from(ip))).body(Body::empty())
+        };
+        for _ in 0..2 {
+            let response = app.handle_request(rsvp_from([192, 0, 2, 1])?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        }
+        let response = app.handle_request(rsvp_from([192, 0, 2, 1])?).await?;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str()?.parse()?;
+        assert!((1..=60).contains(&retry_after), "{}", retry_after);
+
+        // Other clients, and pages, are unaffected
+        let response = app.handle_request(rsvp_from([192, 0, 2, 2])?).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let request = Request::get("/").extension(PeerAddress(IpAddr::from([192, 0, 2, 1]))).body(Body::empty())?;
+        assert_eq!(StatusCode::OK, app.handle_request(request).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn rate_limited_by_forwarded_client() -> Result<()> {
+        let mut app = test_app()?;
+        app.rsvp_rate_limiter = RateLimiter::new(1, Duration::from_secs(60));
+        app.trusted_proxies = vec![IpAddr::from([10, 0, 0, 2])];
+        let rsvp_via_proxy = |client: &str| {
+            Request::post("/enter-rsvp")
+                .header("X-Forwarded-For", client)
+                .extension(PeerAddress(IpAddr::from([10, 0, 0, 2])))
+                .body(Body::empty())
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, app.handle_request(rsvp_via_proxy("203.0.113.7")?).await?.status());
+        assert_eq!(StatusCode::BAD_REQUEST, app.handle_request(rsvp_via_proxy("203.0.113.8")?).await?.status());
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, app.handle_request(rsvp_via_proxy("203.0.113.7")?).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn event_page_needs_trailing_slash() -> Result<()> {
+        let response = test_app()?.handle_request(Request::get("/e/fall-trip").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::PERMANENT_REDIRECT, response.status());
+        assert_eq!("/e/fall-trip/", response.headers()[header::LOCATION]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn content_types() -> Result<()> {
+        let app = test_app()?;
+        for (path, content_type) in [
+            ("/", "text/html; charset=utf-8"),
+            ("/favicon.ico", "image/x-icon"),
+            ("/kayaking-background.webp", "image/webp"),
+            ("/api/time", "application/json"),
+            ("/missing.png", "text/html; charset=utf-8")
+        ] {
+            let response = app.handle_request(Request::get(path).body(Body::empty())?).await?;
+            assert_eq!(content_type, response.headers()[header::CONTENT_TYPE], "{}", path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn status_query() {
+        assert_eq!(Ok((String::from("Mary Ann"), None)), parse_status_query(Some("first_name=Mary+Ann")));
+        assert_eq!(Ok((String::from("Zoë"), Some(InviteId(7)))),
+                   parse_status_query(Some("invite_id=7&first_name=Zo%C3%AB")));
+        assert!(parse_status_query(Some("first_name=Alice&invite_id=0")).is_err());
+    }
+
+    #[test]
+    fn negotiate_formats() -> Result<()> {
+        let formats = |headers: &[(header::HeaderName, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(name, HeaderValue::from_static(value));
+            }
+            let formats = Formats::negotiate(&map);
+            (formats.request, formats.response)
+        };
+        assert_eq!((BodyFormat::Json, BodyFormat::Json), formats(&[]));
+        assert_eq!((BodyFormat::Cbor, BodyFormat::Cbor), formats(&[(header::CONTENT_TYPE, "application/cbor")]));
+        assert_eq!((BodyFormat::Json, BodyFormat::Cbor), formats(&[(header::ACCEPT, "application/cbor")]));
+        assert_eq!((BodyFormat::Cbor, BodyFormat::Json),
+                   formats(&[(header::CONTENT_TYPE, "application/cbor"), (header::ACCEPT, "application/json")]));
+        assert_eq!((BodyFormat::Json, BodyFormat::Json),
+                   formats(&[(header::CONTENT_TYPE, "text/plain"), (header::ACCEPT, "*/*")]));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn rsvp_in_cbor() -> Result<()> {
+        let mut app = test_app()?;
+        app.database = Database::connect_lazy("sqlite::memory:", None, true, PoolSettings::default())?;
+        app.database.migrate().await?;
+        app.database.insert_invite(DEFAULT_EVENT_ID, "Alice", None, None).await?;
+        let rsvp = ClientRSVP {
+            first_name: String::from("Alice"),
+            invite_id: None,
+            details: thebestofcmu_common::RsvpDetails {
+                phone_number: None,
+                email_address: Some(String::from("alice@example.com")),
+                attending: true,
+                guest_count: 1
+            }
+        };
+        let request = Request::post("/enter-rsvp")
+            .header(header::CONTENT_TYPE, "application/cbor")
+            .body(rsvp.clone().encode_as(BodyFormat::Cbor)?)?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::ACCEPTED, response.status());
+        assert_eq!("application/cbor", response.headers()[header::CONTENT_TYPE]);
+        let response = ServerResponse::decode_as(response.into_body(), 1024, BodyFormat::Cbor).await?;
+        assert_eq!(ServerResponse::Success, response);
+
+        // Sent as JSON, answered in CBOR
+        let request = Request::post("/enter-rsvp")
+            .header(header::ACCEPT, "application/cbor")
+            .body(rsvp.clone().encode()?)?;
+        let response = app.handle_request(request).await?;
+        assert_eq!("application/cbor", response.headers()[header::CONTENT_TYPE]);
+        let response = ServerResponse::decode_as(response.into_body(), 1024, BodyFormat::Cbor).await?;
+        assert!(matches!(response, ServerResponse::AlreadyRSVPed(_)), "{:?}", response);
+
+        // Labelled as JSON, so not read as CBOR
+        let request = Request::post("/enter-rsvp")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(rsvp.encode_as(BodyFormat::Cbor)?)?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let request = Request::post("/enter-rsvp")
+            .header(header::CONTENT_TYPE, "application/cbor")
+            .body(Body::from(r#"{"first_name": "Alice"}"#))?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("Unable to parse RSVP cbor", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn undecodable_bodies() -> Result<()> {
+        let app = test_app()?;
+        for (path, kind) in [("/enter-rsvp", "RSVP"), ("/cancel-rsvp", "cancellation")] {
+            let response = app.handle_request(Request::post(path).body(Body::from(vec![b'{', 0xff, b'}']))?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", path);
+            assert_eq!("Request body must be UTF-8", body_string(response).await?);
+
+            let response = app.handle_request(Request::post(path).body(Body::from("{"))?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", path);
+            assert_eq!(format!("Unable to parse {} json", kind), body_string(response).await?);
+
+            let response = app.handle_request(Request::post(path).body(Body::empty())?).await?;
+            assert_eq!("Request body is required", body_string(response).await?);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn status_by_name_alone_hides_details() -> Result<()> {
+        let mut app = test_app()?;
+        app.database = Database::connect_lazy("sqlite::memory:", None, true, PoolSettings::default())?;
+        app.database.migrate().await?;
+        app.database.insert_invite(DEFAULT_EVENT_ID, "Alice", None, None).await?;
+        let rsvp = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "alice@example.com"}}"#;
+        app.handle_request(Request::post("/enter-rsvp").body(Body::from(rsvp))?).await?;
+
+        let response = app.handle_request(Request::get("/rsvp-status?first_name=Alice").body(Body::empty())?).await?;
+        let status: RsvpStatus = serde_json::from_str(&body_string(response).await?)?;
+        assert!(matches!(status, RsvpStatus::RSVPedAt(_)), "{:?}", status);
+
+        let invite_id = app.database.select_invites(DEFAULT_EVENT_ID).await?[0].id;
+        let uri = format!("/rsvp-status?first_name=Alice&invite_id={}", invite_id);
+        let response = app.handle_request(Request::get(uri).body(Body::empty())?).await?;
+        let status: RsvpStatus = serde_json::from_str(&body_string(response).await?)?;
+        match status {
+            RsvpStatus::RSVPed(details, _) => assert_eq!(Some("alice@example.com"), details.email_address.as_deref()),
+            other => panic!("{:?}", other)
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn status_rate_limited() -> Result<()> {
+        let mut app = test_app()?;
+        app.rsvp_rate_limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let lookup_from = |ip: [u8; 4]| {
+            Request::get("/rsvp-status").extension(PeerAddress(IpAddr::from(ip))).body(Body::empty())
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, app.handle_request(lookup_from([192, 0, 2, 1])?).await?.status());
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, app.handle_request(lookup_from([192, 0, 2, 1])?).await?.status());
+        assert_eq!(StatusCode::BAD_REQUEST, app.handle_request(lookup_from([192, 0, 2, 2])?).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn status_requires_first_name() -> Result<()> {
+        let app = test_app()?;
+        for uri in ["/rsvp-status", "/rsvp-status?first_name=", "/rsvp-status?first_name=%20", "/rsvp-status?name=Alice"] {
+            let response = app.handle_request(Request::get(uri).body(Body::empty())?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", uri);
+            assert_eq!("first_name is required", body_string(response).await?);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unreadable_cancellation() -> Result<()> {
+        let request = Request::post("/cancel-rsvp").body(Body::from("{}"))?;
+        let response = test_app()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("Unable to parse cancellation json", body_string(response).await?);
+
+        let request = Request::post("/cancel-rsvp").body(Body::from(r#"{"first_name": "Alice"}"#))?;
+        let response = app_requiring_https()?.handle_request(request).await?;
+        assert_eq!("RSVPs must be sent over HTTPS", body_string(response).await?);
+        Ok(())
+    }
+
+    fn rsvp_body_of_length(length: usize) -> String {
+        let body = r#"{"first_name": "", "details": {"phone_number": null, "email_address": "alice@example.com"}}"#;
+        format!("{}{}", body, " ".repeat(length - body.len()))
+    }
+
+    #[async_std::test]
+    async fn body_within_limit() -> Result<()> {
+        let mut app = test_app()?;
+        app.max_body_bytes = 128;
+        let request = Request::post("/enter-rsvp").body(Body::from(rsvp_body_of_length(128)))?;
+        let response = app.handle_request(request).await?;
+        // Read in full and on to validation
+        assert_eq!("first_name is required", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn body_over_limit() -> Result<()> {
+        let mut app = test_app()?;
+        app.max_body_bytes = 128;
+        for path in ["/enter-rsvp", "/cancel-rsvp"] {
+            let request = Request::post(path).body(Body::from(rsvp_body_of_length(129)))?;
+            assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, app.handle_request(request).await?.status(), "{}", path);
+        }
+        let request = Request::get("/").body(Body::from(rsvp_body_of_length(129)))?;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, app.handle_request(request).await?.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn streamed_body_over_limit() -> Result<()> {
+        // Without a Content-Length, the limit is noticed partway through
+        let stream_chunks = |chunks: usize| {
+            let (mut sender, body) = Body::channel();
+            task::spawn(async move {
+                for _ in 0..chunks {
+                    if sender.send_data(Bytes::from(vec![b' '; 40])).await.is_err() {
+                        // Reading stopped early
+                        break;
+                    }
+                }
+            });
+            body
+        };
+        let mut app = test_app()?;
+        app.max_body_bytes = 128;
+        let response = app.handle_request(Request::post("/enter-rsvp").body(stream_chunks(4))?).await?;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        // Read in full, though it is no RSVP
+        let response = app.handle_request(Request::post("/enter-rsvp").body(stream_chunks(3))?).await?;
+        assert_eq!("Unable to parse RSVP json", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn party_size_out_of_range() -> Result<()> {
+        let app = test_app()?;
+        for guest_count in [0, 5, 255] {
+            let body = format!(r#"{{"first_name": "Alice", "details": {{"phone_number": 4125550123,
+                "email_address": null, "guest_count": {}}}}}"#, guest_count);
+            let response = app.handle_request(Request::post("/enter-rsvp").body(Body::from(body))?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+            assert_eq!("guest_count must be between 1 and 4", body_string(response).await?);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn invalid_rsvp_fields() -> Result<()> {
+        let body = r#"{"first_name": "", "details": {"phone_number": null, "email_address": "a b"}}"#;
+        let request = Request::post("/enter-rsvp").body(Body::from(body))?;
+        let response = test_app()?.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("first_name is required\nemail_address may not contain ' '", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn invalid_phone_numbers() -> Result<()> {
+        let app = test_app()?;
+        for (phone_number, message) in [
+            ("call me", "phone_number may not contain 'c'"),
+            ("+1234567890123456789", "phone_number must be at most 15 characters"),
+            ("555-0123", "Invalid phone number. Numbers outside North America need a country code, such as +44")
+        ] {
+            let body = format!(r#"{{"first_name": "Alice", "details": {{"phone_number": "{}", "email_address": null}}}}"#, phone_number);
+            let response = app.handle_request(Request::post("/enter-rsvp").body(Body::from(body))?).await?;
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", phone_number);
+            assert_eq!(message, body_string(response).await?);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn phone_number_normalized() -> Result<()> {
+        let app = test_app()?;
+        for (phone_number, normalized) in [("\"(412) 555-0123\"", Some("+14125550123")), ("4125550123", Some("+14125550123")),
+                                           ("\"+44 20 7946 0018\"", Some("+442079460018")), ("\"\"", None)] {
+            let body = format!(r#"{{"first_name": "Alice", "details": {{"phone_number": {}, "email_address": "alice@example.com"}}}}"#, phone_number);
+            let rsvp = app.read_rsvp(version::Version::HTTP_11, BodyFormat::Json, Body::from(body)).await?
+                .map_err(|refusal| eyre::eyre!("Refused {}: {:?}", phone_number, refusal))?;
+            assert_eq!(normalized, rsvp.details.phone_number.as_deref(), "{}", phone_number);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn invalid_email_address() -> Result<()> {
+        let body = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "not-an-email"}}"#;
+        let response = test_app()?.handle_request(Request::post("/enter-rsvp").body(Body::from(body))?).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("Invalid email address", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn acceptance_without_contact_info() -> Result<()> {
+        let body = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "", "attending": true}}"#;
+        let response = test_app()?.handle_request(Request::post("/enter-rsvp").body(Body::from(body))?).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("A phone number or email address is required", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn api_schema_served() -> Result<()> {
+        let response = test_app()?.handle_request(Request::get("/api/schema").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(website::JSON_CONTENT_TYPE, response.headers()[header::CONTENT_TYPE]);
+        let schema: serde_json::Value = serde_json::from_str(&body_string(response).await?)?;
+        assert_eq!("string", schema["definitions"]["ClientRSVP"]["properties"]["first_name"]["type"]);
+        assert!(schema["definitions"]["ServerResponse"].is_object());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn metrics_scraped() -> Result<()> {
+        let app = test_app()?;
+        app.handle_request(Request::get("/health").body(Body::empty())?).await?;
+        app.handle_request(Request::get("/no-such-page").body(Body::empty())?).await?;
+        let body = r#"{"first_name": "Alice", "details": {"phone_number": null, "email_address": "not-an-email"}}"#;
+        app.handle_request(Request::post("/enter-rsvp").body(Body::from(body))?).await?;
+
+        let response = app.handle_request(Request::get("/metrics").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(metrics::CONTENT_TYPE, response.headers()[header::CONTENT_TYPE]);
+        let text = body_string(response).await?;
+        assert!(text.contains("thebestofcmu_http_requests_total{method=\"GET\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_requests_total{method=\"POST\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_responses_total{status=\"2xx\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_responses_total{status=\"4xx\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_rsvps_total{outcome=\"rejected\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_count 3\n"), "{}", text);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn metrics_not_served_with_site_when_given_own_address() -> Result<()> {
+        let mut app = test_app()?;
+        app.serves_metrics = false;
+        let response = app.handle_request(Request::get("/metrics").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        Ok(())
+    }
+
+    fn app_requiring_https() -> Result<App> {
+        let mut app = test_app()?;
+        app.require_https_for_rsvp = true;
+        Ok(app)
+    }
+
+    const PROXY: [u8; 4] = [10, 0, 0, 2];
+
+    fn rsvp_request(forwarded_proto: Option<&str>) -> Result<Request<Body>> {
+        let mut request = Request::post("/enter-rsvp").extension(PeerAddress(IpAddr::from(PROXY)));
+        if let Some(forwarded_proto) = forwarded_proto {
+            request = request.header("X-Forwarded-Proto", forwarded_proto);
+        }
+        Ok(request.body(Body::from(r#"{"first_name": "", "details": {"phone_number": null, "email_address": "alice@example.com"}}"#))?)
+    }
+
+    #[async_std::test]
+    async fn rsvp_over_http_rejected() -> Result<()> {
+        let app = app_requiring_https()?;
+        let response = app.handle_request(rsvp_request(None)?).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("RSVPs must be sent over HTTPS", body_string(response).await?);
+
+        // The header is ignored unless the proxy is trusted
+        let response = app.handle_request(rsvp_request(Some("https"))?).await?;
+        assert_eq!("RSVPs must be sent over HTTPS", body_string(response).await?);
+
+        // Pages remain available
+        let response = app.handle_request(Request::get("/").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn rsvp_over_https_allowed() -> Result<()> {
+        let mut app = app_requiring_https()?;
+        app.trusted_proxies = vec![IpAddr::from(PROXY)];
+        let response = app.handle_request(rsvp_request(Some("https"))?).await?;
+        // Past the HTTPS check, on to validation
+        assert_eq!("first_name is required", body_string(response).await?);
+
+        let response = app.handle_request(rsvp_request(Some("http"))?).await?;
+        assert_eq!("RSVPs must be sent over HTTPS", body_string(response).await?);
+
+        app.trusted_proxies.clear();
+        app.serves_https = true;
+        let response = app.handle_request(rsvp_request(None)?).await?;
+        assert_eq!("first_name is required", body_string(response).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn restarting_after_shutdown() -> Result<()> {
+        let app = test_app()?;
+        app.flag_shutdown(async {}).await;
+
+        for request in [Request::get("/").body(Body::empty())?, rsvp_request(None)?] {
+            let response = app.handle_request(request).await?;
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+            assert_eq!("10", response.headers()[header::RETRY_AFTER]);
+            assert!(body_string(response).await?.contains("restarting"));
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn pretty_json_responses() -> Result<()> {
+        let mut app = test_app()?;
+        let now = OffsetDateTime::from_unix_timestamp(1_662_089_400)?;
+        assert!(!app.server_time(now)?.contains('\n'));
+        assert!(!app.to_json(&ServerResponse::AlreadyRSVPed(1_662_089_400))?.contains('\n'));
+
+        app.pretty_json = true;
+        assert!(app.server_time(now)?.lines().count() > 1);
+        let pretty = app.to_json(&ServerResponse::AlreadyRSVPed(1_662_089_400))?;
+        assert!(pretty.lines().count() > 1);
+        assert_eq!(ServerResponse::AlreadyRSVPed(1_662_089_400), serde_json::from_str(&pretty)?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn access_log_line_per_request() -> Result<()> {
+        captured_log::install();
+        let app = test_app()?;
+        let request = Request::get("/access-logged.png")
+            .extension(PeerAddress(IpAddr::from([192, 0, 2, 8])))
+            .body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let records = captured_log::records(|record| {
+            record.target == ACCESS_LOG_TARGET &&
+                record.fields.get("path").map(String::as_str) == Some("/access-logged.png")
+        });
+        assert_eq!(1, records.len());
+        let fields = &records[0].fields;
+        assert_eq!("GET", fields["method"]);
+        assert_eq!("404", fields["status"]);
+        assert_eq!("192.0.2.8", fields["client_ip"]);
+        assert!(fields["elapsed_ms"].parse::<f64>()? >= 0.0);
+        assert!(records[0].message.starts_with("GET /access-logged.png 404 "));
+        Ok(())
+    }
+
+    fn app_for_origins() -> Result<App> {
+        let mut app = test_app()?;
+        app.cors_allowed_origins = vec![String::from("https://rsvp.example.com")];
+        Ok(app)
+    }
+
+    fn preflight_request(origin: &str) -> Result<Request<Body>> {
+        Ok(Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/enter-rsvp")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())?)
+    }
+
+    #[async_std::test]
+    async fn preflight_from_allowed_origin() -> Result<()> {
+        let response = app_for_origins()?.handle_request(preflight_request("https://rsvp.example.com")?).await?;
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        let headers = response.headers();
+        assert_eq!("https://rsvp.example.com", headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+        assert_eq!("GET, HEAD, POST, OPTIONS", headers[header::ACCESS_CONTROL_ALLOW_METHODS]);
+        assert_eq!("Content-Type", headers[header::ACCESS_CONTROL_ALLOW_HEADERS]);
+        assert_eq!("Origin", headers[header::VARY]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn preflight_from_blocked_origin() -> Result<()> {
+        let response = app_for_origins()?.handle_request(preflight_request("https://evil.example.net")?).await?;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // Nothing is allowed unless configured
+        let response = test_app()?.handle_request(preflight_request("https://rsvp.example.com")?).await?;
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn cors_headers_on_post() -> Result<()> {
+        let app = app_for_origins()?;
+        let request = Request::post("/enter-rsvp")
+            .header(header::ORIGIN, "https://rsvp.example.com")
+            .body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("https://rsvp.example.com", response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+
+        let request = Request::post("/enter-rsvp")
+            .header(header::ORIGIN, "https://evil.example.net")
+            .body(Body::empty())?;
+        let response = app.handle_request(request).await?;
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn simple_get_without_cors() -> Result<()> {
+        let response = app_for_origins()?.handle_request(Request::get("/").body(Body::empty())?).await?;
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let request = Request::builder().method(Method::OPTIONS).uri("/").body(Body::empty())?;
+        let response = app_for_origins()?.handle_request(request).await?;
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        assert_eq!("GET, HEAD, POST, OPTIONS", response.headers()[header::ALLOW]);
+        Ok(())
+    }
+}
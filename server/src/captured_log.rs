@@ -0,0 +1,83 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Once};
+use log::{LevelFilter, Log, Metadata, Record};
+use log::kv::{self, Key, Value, Visitor};
+
+/// What a test logged, kept so the test can inspect it
+#[derive(Clone, Debug)]
+pub struct CapturedRecord {
+    pub target: String,
+    pub message: String,
+    pub fields: HashMap<String, String>
+}
+
+static RECORDS: Mutex<Vec<CapturedRecord>> = Mutex::new(Vec::new());
+
+struct CapturedLogger;
+
+impl Log for CapturedLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut fields = HashMap::new();
+        // Visiting only fails if the visitor does
+        let _ = record.key_values().visit(&mut Fields(&mut fields));
+        RECORDS.lock().unwrap().push(CapturedRecord {
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            fields
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+struct Fields<'f>(&'f mut HashMap<String, String>);
+
+impl<'kvs> Visitor<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.as_str().to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// Starts capturing info and more severe records. The logger is global, so tests
+/// should pick out their own records rather than expect to see only those.
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CapturedLogger).unwrap();
+        log::set_max_level(LevelFilter::Info);
+    });
+}
+
+/// Every record captured so far which matches
+pub fn records<P>(predicate: P) -> Vec<CapturedRecord>
+    where P: Fn(&CapturedRecord) -> bool {
+
+    RECORDS.lock().unwrap().iter()
+        .filter(|record| predicate(record))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,203 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::sync::{Arc, RwLock};
+use async_std::channel::{self, Receiver};
+#[cfg(unix)]
+use async_std::channel::TrySendError;
+#[cfg(unix)]
+use async_std::task;
+use eyre::Result;
+use rustls::{Certificate, PrivateKey, SignatureScheme};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+/// Presents whichever certificate was last swapped in, so a renewed certificate is
+/// used for new connections without restarting the server
+pub struct ReloadableCertResolver {
+    current: RwLock<Arc<CertifiedKey>>
+}
+
+impl ReloadableCertResolver {
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        Self { current: RwLock::new(Arc::new(certified_key)) }
+    }
+
+    /// Uses the certificate for every handshake from now on. Connections already
+    /// established keep the certificate they were given.
+    pub fn swap(&self, certified_key: CertifiedKey) {
+        *self.current.write().unwrap() = Arc::new(certified_key);
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Pairs a certificate chain with its private key, failing if the chain is empty, the
+/// key is of a kind rustls cannot sign with, or the key does not belong to the leaf
+pub fn certified_key(certificates: Vec<Certificate>, private_key: &PrivateKey) -> Result<CertifiedKey> {
+    let leaf = certificates.first()
+        .ok_or_else(|| eyre::eyre!("No certificates found"))?;
+    let signing_key = sign::any_supported_type(private_key)
+        .map_err(|_| eyre::eyre!("Unsupported private key"))?;
+    verify_key_matches(leaf, &*signing_key)?;
+    Ok(CertifiedKey::new(certificates, signing_key))
+}
+
+// The schemes rustls signs handshakes with, alongside how webpki verifies each
+static PROBE_SCHEMES: [(SignatureScheme, &webpki::SignatureAlgorithm); 5] = [
+    (SignatureScheme::ED25519, &webpki::ED25519),
+    (SignatureScheme::ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256),
+    (SignatureScheme::ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384),
+    (SignatureScheme::RSA_PSS_SHA256, &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
+    (SignatureScheme::RSA_PKCS1_SHA256, &webpki::RSA_PKCS1_2048_8192_SHA256)
+];
+
+// Signs a probe with the key and verifies it with the leaf's public key. Otherwise a
+// certificate renewed without its key would be swapped in, failing every handshake.
+fn verify_key_matches(leaf: &Certificate, signing_key: &dyn sign::SigningKey) -> Result<()> {
+    const PROBE: &[u8] = b"thebestofcmu certificate and key probe";
+
+    let offered: Vec<SignatureScheme> = PROBE_SCHEMES.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = signing_key.choose_scheme(&offered)
+        .ok_or_else(|| eyre::eyre!("Unsupported private key"))?;
+    let algorithm = PROBE_SCHEMES.iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .map(|(_, algorithm)| *algorithm)
+        .ok_or_else(|| eyre::eyre!("Unsupported private key"))?;
+    let signature = signer.sign(PROBE)
+        .map_err(|e| eyre::eyre!("Unable to sign with the private key: {}", e))?;
+    let leaf = webpki::EndEntityCert::try_from(leaf.0.as_slice())
+        .map_err(|e| eyre::eyre!("Invalid certificate: {}", e))?;
+    leaf.verify_signature(algorithm, PROBE, &signature)
+        .map_err(|_| eyre::eyre!("The private key does not belong to the certificate"))
+}
+
+/// Hears each SIGHUP, which asks for the certificate to be reloaded
+#[cfg(unix)]
+pub fn hangups() -> Result<Receiver<()>> {
+    let mut signals = Signals::new([SIGHUP])?;
+    let (sender, hangups) = channel::bounded(1);
+    task::spawn_blocking(move || {
+        for _ in signals.forever() {
+            // A full channel already has a reload waiting
+            if let Err(TrySendError::Closed(_)) = sender.try_send(()) {
+                break;
+            }
+        }
+    });
+    Ok(hangups)
+}
+
+/// There are no hangups to hear on this platform, so the receiver is closed
+#[cfg(not(unix))]
+pub fn hangups() -> Result<Receiver<()>> {
+    let (_, hangups) = channel::bounded(1);
+    Ok(hangups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::io;
+    use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+
+    fn self_signed() -> Result<(Certificate, PrivateKey)> {
+        let generated = rcgen::generate_simple_self_signed(vec![String::from("localhost")])?;
+        Ok((Certificate(generated.serialize_der()?), PrivateKey(generated.serialize_private_key_der())))
+    }
+
+    // Handshakes in memory, yielding the certificate the server presented
+    fn handshake(server_config: &Arc<ServerConfig>, trusted: &[&Certificate]) -> Result<Certificate> {
+        let mut roots = RootCertStore::empty();
+        for certificate in trusted {
+            roots.add(certificate)?;
+        }
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut client = Connection::from(
+            ClientConnection::new(Arc::new(client_config), rustls::ServerName::try_from("localhost")?)?
+        );
+        let mut server = Connection::from(ServerConnection::new(server_config.clone())?);
+        while client.is_handshaking() || server.is_handshaking() {
+            transfer(&mut client, &mut server)?;
+            transfer(&mut server, &mut client)?;
+        }
+        let presented = client.peer_certificates()
+            .and_then(|chain| chain.first())
+            .ok_or_else(|| eyre::eyre!("No certificate presented"))?;
+        Ok(presented.clone())
+    }
+
+    fn transfer(from: &mut Connection, to: &mut Connection) -> Result<()> {
+        let mut buffer = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut buffer)?;
+        }
+        let mut reader = io::Cursor::new(buffer);
+        while (reader.position() as usize) < reader.get_ref().len() {
+            to.read_tls(&mut reader)?;
+        }
+        to.process_new_packets()?;
+        Ok(())
+    }
+
+    #[test]
+    fn swapped_certificate_used_for_new_handshakes() -> Result<()> {
+        let (first_certificate, first_key) = self_signed()?;
+        let (second_certificate, second_key) = self_signed()?;
+        let resolver = Arc::new(ReloadableCertResolver::new(
+            certified_key(vec![first_certificate.clone()], &first_key)?
+        ));
+        let server_config = Arc::new(ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone()));
+        let trusted = [&first_certificate, &second_certificate];
+
+        assert_eq!(first_certificate, handshake(&server_config, &trusted)?);
+        resolver.swap(certified_key(vec![second_certificate.clone()], &second_key)?);
+        assert_eq!(second_certificate, handshake(&server_config, &trusted)?);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_certificate() -> Result<()> {
+        let (certificate, _) = self_signed()?;
+        assert!(certified_key(Vec::new(), &self_signed()?.1).is_err());
+        assert!(certified_key(vec![certificate.clone()], &PrivateKey(b"not a key".to_vec())).is_err());
+        // As when a renewal replaces the certificate but not its key
+        let (_, other_key) = self_signed()?;
+        match certified_key(vec![certificate], &other_key) {
+            Err(e) => assert!(e.to_string().contains("does not belong"), "{}", e),
+            Ok(_) => panic!("Accepted a key which does not belong to the certificate")
+        }
+        Ok(())
+    }
+}
@@ -20,72 +20,836 @@
 
 use std::fmt::Arguments;
 use std::mem;
+use std::time::{Duration, SystemTime};
 use eyre::Result;
-use async_std::io::{Stdin, Stdout, WriteExt};
-use time::format_description::FormatItem;
-use time::OffsetDateTime;
-use thebestofcmu_common::Invitee;
+use async_std::fs;
+use async_std::io::{BufRead, BufReadExt, BufReader, Stdin, Stdout, Write, WriteExt};
+use time::format_description;
+use time::{OffsetDateTime, UtcOffset};
+use thebestofcmu_common::{InviteId, Invitee};
 use crate::Database;
+use crate::csv;
+use crate::database::{ConnectionLost, InviteOutcome, InviteSort};
+use crate::event;
+use crate::report;
 
-pub struct Cli {
-    pub stdin: Stdin,
-    pub stdout: Stdout,
-    pub database: Database
+const DEFAULT_PAGE_SIZE: u32 = 20;
+
+const COMMANDS: &str = "invite, list-invites [page size] [id|name|rsvp], search-invite, stats, source-stats, list-rsvp-days, note <id>, delete-invite, report, import-csv, export-csv, add-event, quit";
+
+pub const ONE_SHOT_USAGE: &str =
+    "Usage: thebestofcmu-server cli [--event <slug>] [invite <name> | list | export-csv [path] | stats]";
+
+/// A command given as arguments, which runs once in place of the prompt so that it can be scripted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OneShot {
+    Invite(String),
+    List,
+    /// Prints the CSV unless given a path to write it to
+    ExportCsv(Option<String>),
+    Stats
+}
+
+impl OneShot {
+    /// Parses the arguments after cli, skipping --event and its slug. Yields None without a
+    /// command, in which case the prompt is shown.
+    pub fn parse(args: &[String]) -> Result<Option<Self>> {
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--event" {
+                args.next();
+            } else {
+                positional.push(arg.as_str());
+            }
+        }
+        Ok(Some(match positional.as_slice() {
+            [] => return Ok(None),
+            ["invite", first_name] if !first_name.trim().is_empty() => Self::Invite(first_name.trim().to_string()),
+            ["list"] => Self::List,
+            ["export-csv"] => Self::ExportCsv(None),
+            ["export-csv", path] => Self::ExportCsv(Some(path.to_string())),
+            ["stats"] => Self::Stats,
+            _ => return Err(eyre::eyre!("Invalid command {}\n{}", positional.join(" "), ONE_SHOT_USAGE))
+        }))
+    }
+}
+
+pub struct Cli<I = BufReader<Stdin>, O = Stdout> {
+    pub stdin: I,
+    pub stdout: O,
+    pub database: Database,
+    pub utc_offset: UtcOffset,
+    /// The event whose invitees and RSVPs are managed
+    pub event_id: i32
 }
 
-impl Cli {
+impl<I, O> Cli<I, O>
+    where I: BufRead + Unpin,
+          O: Write + Unpin {
 
-    pub async fn start(mut self) -> Result<()> {
+    /// Runs commands until quit or exit is entered, or the input ends
+    pub async fn start(&mut self) -> Result<()> {
 
         let mut buffer = String::new();
         loop {
-            self.stdout.write_all(b"Enter command: invite, list-invites").await?;
-            self.stdin.read_line(&mut buffer).await?;
-            match buffer.as_str() {
-                "invite" => {
-
-                    self.stdout.write_all(b"Enter invitee name\n").await?;
-                    buffer.clear();
-                    self.stdin.read_line(&mut buffer).await?;
-                    self.database.insert_invite(&buffer).await?;
-
-                    self.stdout.write_fmt(format_args!("Invited {}\n", &buffer)).await?;
+            self.stdout.write_fmt(format_args!("Enter command: {}\n", COMMANDS)).await?;
+            if self.stdin.read_line(&mut buffer).await? == 0 {
+                return Ok(());
+            }
+            let line = buffer.trim();
+            match split_command(line) {
+                ("quit" | "exit", "") => {
+                    return Ok(());
+                },
+                ("invite", "") => {
+                    self.invite().await?;
                 },
-                "list-invites" => {
-                    self.list_invites().await?;
+                ("list-invites", options) => {
+                    self.list_invites(options).await?;
+                },
+                ("search-invite", "") => {
+                    self.search_invite().await?;
+                },
+                ("stats", "") => {
+                    self.stats().await?;
+                },
+                ("source-stats", "") => {
+                    self.source_stats().await?;
+                },
+                ("list-rsvp-days", "") => {
+                    self.list_rsvp_days().await?;
+                },
+                ("add-event", "") => {
+                    self.add_event().await?;
+                },
+                ("delete-invite", "") => {
+                    self.delete_invite().await?;
+                },
+                ("note", id) => {
+                    self.note(id).await?;
+                },
+                ("import-csv", "") => {
+                    self.import_csv().await?;
+                },
+                ("export-csv", "") => {
+                    self.export_csv().await?;
+                },
+                ("report", "") => {
+                    self.report().await?;
                 }
-                other => {
-                    self.stdout.write_fmt(format_args!("Unknown command {}\n", other)).await?;
+                _ => {
+                    self.stdout.write_fmt(format_args!("Unknown command {}. Available commands: {}\n", line, COMMANDS)).await?;
                 }
             }
             buffer.clear();
         }
     }
 
-    async fn list_invites(&mut self) -> Result<()> {
+    /// Runs the command given as arguments. Anything which would be reported at the prompt,
+    /// such as an invitee being invited already, fails instead, so that scripts notice.
+    pub async fn run_once(&mut self, command: OneShot) -> Result<()> {
+        match command {
+            OneShot::Invite(first_name) => {
+                match self.database.insert_invite(self.event_id, &first_name, None, None).await? {
+                    InviteOutcome::Invited => {
+                        self.stdout.write_fmt(format_args!("Invited {}\n", first_name)).await?;
+                    },
+                    InviteOutcome::AlreadyInvited(first_name) => {
+                        return Err(eyre::eyre!("{} is already invited", first_name));
+                    }
+                }
+            },
+            OneShot::List => {
+                let invitees = self.database.select_invites(self.event_id).await?;
+                self.write_invitees(invitees).await?;
+            },
+            OneShot::ExportCsv(path) => {
+                self.write_csv(path.as_deref().unwrap_or_default()).await?;
+            },
+            OneShot::Stats => {
+                self.stats().await?;
+            }
+        }
+        Ok(self.stdout.flush().await?)
+    }
+
+    async fn invite(&mut self) -> Result<()> {
+
+        self.stdout.write_all(b"Enter invitee name\n").await?;
+        let mut first_name = String::new();
+        self.stdin.read_line(&mut first_name).await?;
+        let first_name = first_name.trim();
+
+        self.stdout.write_all(b"Enter invite source (text, email, in person), or leave blank\n").await?;
+        let mut source = String::new();
+        self.stdin.read_line(&mut source).await?;
+        let source = Some(source.trim()).filter(|source| !source.is_empty());
+
+        self.stdout.write_all(b"Enter how long the invite lasts (e.g. 7d, 12h, 30m), or leave blank\n").await?;
+        let mut expiry = String::new();
+        self.stdin.read_line(&mut expiry).await?;
+        let expires_at = match expiry.trim() {
+            "" => None,
+            expiry => match parse_expiry(expiry) {
+                Some(lasts_for) => Some(SystemTime::now() + lasts_for),
+                None => {
+                    self.stdout.write_fmt(format_args!("Invalid duration {}\n", expiry)).await?;
+                    return Ok(());
+                }
+            }
+        };
+        match self.database.insert_invite(self.event_id, first_name, source, expires_at).await? {
+            InviteOutcome::Invited => {
+                self.stdout.write_fmt(format_args!("Invited {}\n", first_name)).await?;
+            },
+            InviteOutcome::AlreadyInvited(first_name) => {
+                self.stdout.write_fmt(format_args!("{} is already invited\n", first_name)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_invites(&mut self, options: &str) -> Result<()> {
+        let (page_size, sort) = match parse_list_options(options) {
+            Ok(options) => options,
+            Err(e) => {
+                self.stdout.write_fmt(format_args!("{}. Usage: list-invites [page size] [id|name|rsvp]\n", e)).await?;
+                return Ok(());
+            }
+        };
+        let mut page: u32 = 0;
+        let mut answer = String::new();
+        loop {
+            let invitees = match page.checked_mul(page_size) {
+                Some(offset) => self.select_invites_page(page_size, offset, sort).await?,
+                // No page starts beyond the largest offset
+                None => Vec::new()
+            };
+            if invitees.is_empty() && page > 0 {
+                self.stdout.write_all(b"No more invitees\n").await?;
+                page -= 1;
+            } else {
+                self.write_invitees(invitees).await?;
+                self.stdout.write_fmt(format_args!("Page {}\n", page + 1)).await?;
+            }
+            self.stdout.write_all(b"Enter n for the next page, p for the previous, or leave blank to stop\n").await?;
+            answer.clear();
+            self.stdin.read_line(&mut answer).await?;
+            match answer.trim() {
+                "n" => page += 1,
+                "p" if page > 0 => page -= 1,
+                "p" => {},
+                _ => return Ok(())
+            }
+        }
+    }
+
+    async fn select_invites_page(&mut self, limit: u32, offset: u32, sort: InviteSort) -> Result<Vec<Invitee>> {
+        match self.database.select_invites_page(self.event_id, limit, offset, sort).await {
+            Ok(invitees) => Ok(invitees),
+            Err(e) => {
+                let rows_read = e.downcast_ref::<ConnectionLost>().map(|lost| lost.rows_read);
+                match rows_read {
+                    Some(rows_read) => {
+                        self.stdout.write_fmt(format_args!(
+                            "Connection lost after {} rows. Retrying once...\n", rows_read)).await?;
+                        self.database.select_invites_page(self.event_id, limit, offset, sort).await
+                    },
+                    None => Err(e)
+                }
+            }
+        }
+    }
+
+    async fn search_invite(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter part of the invitee's name\n").await?;
+        let mut name = String::new();
+        self.stdin.read_line(&mut name).await?;
+        let name = name.trim();
+        if name.is_empty() {
+            self.stdout.write_all(b"Nothing to search for. Use list-invites to see every invitee\n").await?;
+            return Ok(());
+        }
+        let invitees = self.database.search_invites_by_name(self.event_id, name).await?;
+        if invitees.is_empty() {
+            self.stdout.write_fmt(format_args!("No invitee's name contains {}\n", name)).await?;
+            return Ok(());
+        }
+        self.write_invitees(invitees).await
+    }
+
+    // Writes the table shown by list-invites
+    async fn write_invitees(&mut self, invitees: Vec<Invitee>) -> Result<()> {
         let stdout = &mut self.stdout;
 
-        stdout.write_all(b"ID | Name | RSVP'd?\n").await?;
+        stdout.write_all(b"ID | Name | Source | Note | Invited at | RSVP'd?\n").await?;
 
-        for mut invitee in self.database.select_invites().await? {
+        for mut invitee in invitees {
 
-            async fn write_rsvp(stdout: &mut Stdout, invitee: Invitee, rsvp: Arguments<'_>) -> Result<()> {
+            async fn write_rsvp<O>(stdout: &mut O, columns: &str, rsvp: Arguments<'_>) -> Result<()>
+                where O: Write + Unpin {
                 Ok(stdout.write_fmt(
-                    format_args!("{} | {} | {}\n", invitee.id, invitee.first_name, rsvp)
+                    format_args!("{} | {}\n", columns, rsvp)
                 ).await?)
             }
+            let columns = invitee_columns(&invitee, self.utc_offset)?;
             match mem::replace(&mut invitee.rsvp, None) {
-                None => write_rsvp(&mut *stdout, invitee, format_args!("No")).await,
+                None => write_rsvp(&mut *stdout, &columns, format_args!("No")).await,
                 Some((details, at_time)) => {
-                    let at_time: OffsetDateTime = at_time.into();
-                    let at_time = at_time.format(&FormatItem::Literal(b"%d/%m/%Y %T"))?;
-                    write_rsvp(&mut *stdout, invitee,
-                               format_args!("Yes, at date: {}. Details: \n    {}", at_time, details)).await
+                    let at_time = format_time(at_time, self.utc_offset)?;
+                    if details.attending {
+                        write_rsvp(&mut *stdout, &columns,
+                                   format_args!("Yes, at date: {}. Details: \n    {}", at_time, details)).await
+                    } else {
+                        write_rsvp(&mut *stdout, &columns, format_args!("Declined, at date: {}", at_time)).await
+                    }
                 }
             }?;
         }
         Ok(())
     }
 
+    async fn note(&mut self, id: &str) -> Result<()> {
+        let id: InviteId = match id.parse() {
+            Ok(id) => id,
+            Err(e) => {
+                self.stdout.write_fmt(format_args!("{}. Usage: note <id>\n", e)).await?;
+                return Ok(());
+            }
+        };
+        self.stdout.write_all(b"Enter the note, or leave blank to clear it\n").await?;
+        let mut note = String::new();
+        self.stdin.read_line(&mut note).await?;
+        let note = parse_note(&note);
+
+        if !self.database.update_admin_note(id, note).await? {
+            self.stdout.write_fmt(format_args!("No invitee with ID {}\n", id)).await?;
+        } else if note.is_some() {
+            self.stdout.write_fmt(format_args!("Set the note on invitee {}\n", id)).await?;
+        } else {
+            self.stdout.write_fmt(format_args!("Cleared the note on invitee {}\n", id)).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_event(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter the event's slug, as in /e/{slug}/ (lowercase letters, digits and dashes)\n").await?;
+        let mut slug = String::new();
+        self.stdin.read_line(&mut slug).await?;
+        let slug = slug.trim();
+        if !event::valid_slug(slug) {
+            self.stdout.write_fmt(format_args!("Invalid slug {}\n", slug)).await?;
+            return Ok(());
+        }
+
+        self.stdout.write_all(b"Enter the page heading as HTML, or leave blank for the default\n").await?;
+        let mut title_html = String::new();
+        self.stdin.read_line(&mut title_html).await?;
+        let title_html = Some(title_html.trim()).filter(|title_html| !title_html.is_empty());
+
+        self.stdout.write_all(b"Enter the date, place and cost as HTML, or leave blank for the default\n").await?;
+        let mut details_html = String::new();
+        self.stdin.read_line(&mut details_html).await?;
+        let details_html = Some(details_html.trim()).filter(|details_html| !details_html.is_empty());
+
+        let event_id = self.database.insert_event(slug, title_html, details_html).await?;
+        self.stdout.write_fmt(format_args!(
+            "Added event {} with ID {}. Manage it with --event {}\n", slug, event_id, slug
+        )).await?;
+        Ok(())
+    }
+
+    async fn delete_invite(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter the ID of the invitee to delete, as shown by list-invites\n").await?;
+        let mut id = String::new();
+        self.stdin.read_line(&mut id).await?;
+        let id: InviteId = match id.trim().parse() {
+            Ok(id) => id,
+            Err(e) => {
+                self.stdout.write_fmt(format_args!("{}. Nothing was deleted\n", e)).await?;
+                return Ok(());
+            }
+        };
+        if self.database.delete_invite(id).await? {
+            self.stdout.write_fmt(format_args!("Deleted invitee {} and any RSVP of theirs\n", id)).await?;
+        } else {
+            self.stdout.write_fmt(format_args!("No invitee with ID {}\n", id)).await?;
+        }
+        Ok(())
+    }
+
+    async fn import_csv(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter the path of a CSV file with one first name per row\n").await?;
+        let mut path = String::new();
+        self.stdin.read_line(&mut path).await?;
+        let path = path.trim();
+
+        // A mistyped path should not end the session
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.stdout.write_fmt(format_args!("Unable to read {}: {}. No one was invited\n", path, e)).await?;
+                return Ok(());
+            }
+        };
+        let first_names = match csv::parse_first_names(&contents) {
+            Ok(first_names) => first_names,
+            Err(e) => {
+                self.stdout.write_fmt(format_args!("{}. No one was invited\n", e)).await?;
+                return Ok(());
+            }
+        };
+        match self.database.insert_invites(self.event_id, &first_names, None).await? {
+            InviteOutcome::Invited => {
+                self.stdout.write_fmt(format_args!("Invited {} people from {}\n", first_names.len(), path)).await?;
+            },
+            InviteOutcome::AlreadyInvited(first_name) => {
+                self.stdout.write_fmt(format_args!("{} is already invited. No one was invited\n", first_name)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn report(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter the path to write the report to\n").await?;
+        let mut path = String::new();
+        self.stdin.read_line(&mut path).await?;
+        let path = path.trim();
+        if path.is_empty() {
+            self.stdout.write_all(b"A path is required. No report was written\n").await?;
+            return Ok(());
+        }
+
+        let invitees = self.database.select_invites(self.event_id).await?;
+        let sources = self.database.select_stats_by_source(self.event_id).await?;
+        if let Err(e) = fs::write(path, report::render_report(&invitees, &sources)).await {
+            self.stdout.write_fmt(format_args!("Unable to write {}: {}. No report was written\n", path, e)).await?;
+            return Ok(());
+        }
+        self.stdout.write_fmt(format_args!("Wrote report to {}\n", path)).await?;
+        Ok(())
+    }
+
+    async fn export_csv(&mut self) -> Result<()> {
+        self.stdout.write_all(b"Enter the path to write the CSV to, or leave blank to print it\n").await?;
+        let mut path = String::new();
+        self.stdin.read_line(&mut path).await?;
+        self.write_csv(path.trim()).await
+    }
+
+    // Prints the CSV if the path is empty
+    async fn write_csv(&mut self, path: &str) -> Result<()> {
+        let invitees = self.database.select_invites(self.event_id).await?;
+        let csv = csv::render_invitees(&invitees, self.utc_offset)?;
+        if path.is_empty() {
+            self.stdout.write_all(csv.as_bytes()).await?;
+        } else {
+            fs::write(path, csv).await?;
+            self.stdout.write_fmt(format_args!("Wrote {} invitees to {}\n", invitees.len(), path)).await?;
+        }
+        Ok(())
+    }
+
+    async fn stats(&mut self) -> Result<()> {
+        let summary = self.database.select_rsvp_summary(self.event_id).await?;
+        self.stdout.write_all(b"Invited | Attending | Declined | Not yet RSVP'd | Headcount\n").await?;
+        self.stdout.write_fmt(format_args!(
+            "{} | {} | {} | {} | {}\n",
+            summary.invited, summary.attending, summary.declined, summary.not_responded, summary.headcount
+        )).await?;
+        Ok(())
+    }
+
+    async fn source_stats(&mut self) -> Result<()> {
+        let stdout = &mut self.stdout;
+
+        stdout.write_all(b"Source | Invited | RSVP'd | RSVP rate\n").await?;
+
+        for stats in self.database.select_stats_by_source(self.event_id).await? {
+            let source = stats.source.as_deref().unwrap_or("-");
+            stdout.write_fmt(format_args!(
+                "{} | {} | {} | {:.0}%\n", source, stats.invited, stats.rsvped, stats.rsvp_rate() * 100.0
+            )).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_rsvp_days(&mut self) -> Result<()> {
+        let stdout = &mut self.stdout;
+
+        stdout.write_all(b"Day | RSVPs\n").await?;
+
+        for (day, count) in self.database.select_rsvp_days(self.event_id, self.utc_offset).await? {
+            stdout.write_fmt(format_args!("{} | {}\n", day, count)).await?;
+        }
+        Ok(())
+    }
+
+}
+
+// Everything but whether the invitee has RSVPed, as listed by list-invites
+fn invitee_columns(invitee: &Invitee, utc_offset: UtcOffset) -> Result<String> {
+    let source = invitee.source.as_deref().unwrap_or("-");
+    let note = invitee.admin_note.as_deref().unwrap_or("-");
+    let created_at = match invitee.created_at {
+        Some(created_at) => format_time(created_at, utc_offset)?,
+        None => String::from("-")
+    };
+    Ok(format!("{} | {} | {} | {} | {}", invitee.id, invitee.first_name, source, note, created_at))
+}
+
+// Day first, in the configured offset from UTC
+fn format_time(at_time: SystemTime, utc_offset: UtcOffset) -> Result<String> {
+    let format = format_description::parse("[day]/[month]/[year] [hour]:[minute]:[second]")?;
+    let at_time: OffsetDateTime = at_time.into();
+    Ok(at_time.to_offset(utc_offset).format(&format)?)
 }
 
+/// Parses the page size and sort key given to list-invites, in either order
+fn parse_list_options(options: &str) -> Result<(u32, InviteSort)> {
+    let mut page_size = DEFAULT_PAGE_SIZE;
+    let mut sort = InviteSort::Id;
+    for option in options.split_whitespace() {
+        if option.starts_with(|c: char| c.is_ascii_digit()) {
+            page_size = option.parse()
+                .map_err(|e| eyre::eyre!("Invalid page size {}: {}", option, e))?;
+            if page_size == 0 {
+                return Err(eyre::eyre!("Invalid page size 0: must be positive"));
+            }
+        } else {
+            sort = option.parse()?;
+        }
+    }
+    Ok((page_size, sort))
+}
+
+/// Splits a command line into its first word and the trimmed remainder
+fn split_command(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((command, args)) => (command, args.trim()),
+        None => (line, "")
+    }
+}
+
+/// A blank note clears any existing one
+fn parse_note(note: &str) -> Option<&str> {
+    Some(note.trim()).filter(|note| !note.is_empty())
+}
+
+/// Parses a duration such as "7d", "12h" or "30m"
+fn parse_expiry(expiry: &str) -> Option<Duration> {
+    let unit_secs = match expiry.chars().next_back()? {
+        'd' => 24 * 60 * 60,
+        'h' => 60 * 60,
+        'm' => 60,
+        _ => return None
+    };
+    let amount: u64 = expiry[..expiry.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(amount.checked_mul(unit_secs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::Cursor;
+    use crate::database::PoolSettings;
+    use crate::event::DEFAULT_EVENT_ID;
+
+    // Feeds the script to the CLI, yielding what it wrote. The script must not
+    // reach the database, which is not running.
+    async fn run_script(script: &str) -> Result<String> {
+        let database = Database::connect_lazy("postgres://localhost/thebestofcmu", None, true, PoolSettings::default())?;
+        run_script_on(database, script).await
+    }
+
+    async fn run_script_on(database: Database, script: &str) -> Result<String> {
+        let mut cli = Cli {
+            stdin: Cursor::new(script.as_bytes().to_vec()),
+            stdout: Vec::new(),
+            database,
+            utc_offset: UtcOffset::UTC,
+            event_id: DEFAULT_EVENT_ID
+        };
+        cli.start().await?;
+        Ok(String::from_utf8(cli.stdout)?)
+    }
+
+    #[async_std::test]
+    async fn quit_ends_loop() -> Result<()> {
+        for quit in ["quit", "exit", " quit "] {
+            // Were the loop to continue, stats would reach the database
+            let output = run_script(&format!("{}\nstats\n", quit)).await?;
+            assert_eq!(format!("Enter command: {}\n", COMMANDS), output);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn end_of_input_ends_loop() -> Result<()> {
+        assert_eq!(format!("Enter command: {}\n", COMMANDS), run_script("").await?);
+        // The last line need not end with a newline
+        let output = run_script("stat").await?;
+        assert_eq!(2, output.matches("Enter command:").count());
+        Ok(())
+    }
+
+    async fn in_memory_database() -> Result<Database> {
+        let database = Database::connect_lazy("sqlite::memory:", None, true, PoolSettings::default())?;
+        database.migrate().await?;
+        Ok(database)
+    }
+
+    #[async_std::test]
+    async fn commands_despite_newline() -> Result<()> {
+        let output = run_script("invite\nAlice\ntext\n7w\nnote x\n").await?;
+        assert!(output.contains("Enter invitee name\n"), "{}", output);
+        assert!(output.contains("Invalid duration 7w\n"), "{}", output);
+        assert!(output.contains("Usage: note <id>\n"), "{}", output);
+        assert!(!output.contains("Unknown command"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn commands_with_surrounding_whitespace() -> Result<()> {
+        let output = run_script("  add-event \r\nNot A Slug\n").await?;
+        assert!(output.contains("Invalid slug Not A Slug\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn delete_invalid_invite_id() -> Result<()> {
+        let output = run_script("delete-invite\nAlice\ndelete-invite\n-3\n").await?;
+        assert!(output.contains("Invalid invite ID Alice: invalid digit found in string. Nothing was deleted\n"),
+                "{}", output);
+        assert!(output.contains("Invalid invite ID -3: must be positive. Nothing was deleted\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn search_for_nothing() -> Result<()> {
+        let output = run_script("search-invite\n  \n").await?;
+        assert!(output.contains("Nothing to search for"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn import_malformed_csv() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("invitees.csv");
+        std::fs::write(&path, "first_name\nAlice\n\"Bob\n")?;
+        let output = run_script(&format!("import-csv\n{}\n", path.display())).await?;
+        assert!(output.contains("Line 3: quoted field never closed. No one was invited\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn file_errors_keep_session() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let missing = directory.path().join("missing.csv");
+        let unwritable = directory.path().join("no-such-directory").join("report.html");
+        let script = format!("import-csv\n{}\nreport\n\nreport\n{}\nquit\n", missing.display(), unwritable.display());
+        let output = run_script_on(in_memory_database().await?, &script).await?;
+        assert!(output.contains(&format!("Unable to read {}: ", missing.display())), "{}", output);
+        assert!(output.contains(". No one was invited\n"), "{}", output);
+        assert!(output.contains("A path is required. No report was written\n"), "{}", output);
+        assert!(output.contains(&format!("Unable to write {}: ", unwritable.display())), "{}", output);
+        // The prompt came back after each
+        assert_eq!(4, output.matches("Enter command:").count());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn invite_twice() -> Result<()> {
+        let output = run_script_on(in_memory_database().await?, "invite\nAlice\n\n\ninvite\nALICE\ntext\n\n").await?;
+        assert!(output.contains("Invited Alice\n"), "{}", output);
+        assert!(output.contains("ALICE is already invited\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn page_through_invites() -> Result<()> {
+        let database = in_memory_database().await?;
+        for first_name in ["Carol", "Alice", "Bob"] {
+            database.insert_invite(DEFAULT_EVENT_ID, first_name, None, None).await?;
+        }
+        let output = run_script_on(database, "list-invites 2 name\nn\nn\np\n\n").await?;
+        let pages: Vec<&str> = output.split("Enter n for the next page").collect();
+        assert_eq!(5, pages.len(), "{}", output);
+        assert!(pages[0].contains("| Alice |") && pages[0].contains("| Bob |") && pages[0].contains("Page 1\n"), "{}", output);
+        assert!(pages[1].contains("| Carol |") && !pages[1].contains("| Alice |") && pages[1].contains("Page 2\n"), "{}", output);
+        // The last page is empty, so the one before stays current
+        assert!(pages[2].contains("No more invitees\n"), "{}", output);
+        assert!(pages[3].contains("Page 1\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn stats_table() -> Result<()> {
+        let database = in_memory_database().await?;
+        database.insert_invite(DEFAULT_EVENT_ID, "Alice", None, None).await?;
+        let output = run_script_on(database, "stats\n").await?;
+        assert!(output.contains("Invited | Attending | Declined | Not yet RSVP'd | Headcount\n1 | 0 | 0 | 1 | 0\n"), "{}", output);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_one_shot() -> Result<()> {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().copied().map(String::from).collect() };
+        assert_eq!(None, OneShot::parse(&[])?);
+        assert_eq!(None, OneShot::parse(&args(&["--event", "fall-trip"]))?);
+        assert_eq!(Some(OneShot::Invite(String::from("Alice"))), OneShot::parse(&args(&["invite", " Alice "]))?);
+        assert_eq!(Some(OneShot::List), OneShot::parse(&args(&["--event", "list", "list"]))?);
+        assert_eq!(Some(OneShot::ExportCsv(Some(String::from("out.csv")))),
+                   OneShot::parse(&args(&["export-csv", "out.csv", "--event", "fall-trip"]))?);
+        assert_eq!(Some(OneShot::ExportCsv(None)), OneShot::parse(&args(&["export-csv"]))?);
+
+        for invalid in [&["invite"][..], &["invite", ""], &["invite", "Alice", "Bob"], &["list-invites"]] {
+            let error = OneShot::parse(&args(invalid)).unwrap_err().to_string();
+            assert!(error.contains(ONE_SHOT_USAGE), "{}", error);
+        }
+        Ok(())
+    }
+
+    async fn run_once_on(database: Database, command: OneShot) -> Result<String> {
+        let mut cli = Cli {
+            stdin: Cursor::new(Vec::new()),
+            stdout: Vec::new(),
+            database,
+            utc_offset: UtcOffset::UTC,
+            event_id: DEFAULT_EVENT_ID
+        };
+        cli.run_once(command).await?;
+        Ok(String::from_utf8(cli.stdout)?)
+    }
+
+    #[async_std::test]
+    async fn one_shot_commands() -> Result<()> {
+        let database = in_memory_database().await?;
+        let output = run_once_on(database.clone(), OneShot::Invite(String::from("Alice"))).await?;
+        assert_eq!("Invited Alice\n", output);
+
+        let error = run_once_on(database.clone(), OneShot::Invite(String::from("alice"))).await.unwrap_err();
+        assert_eq!("alice is already invited", error.to_string());
+
+        let output = run_once_on(database.clone(), OneShot::List).await?;
+        assert!(output.contains("| Alice |"), "{}", output);
+        // Nothing is prompted for
+        assert!(!output.contains("Enter"), "{}", output);
+
+        let parent = tempfile::tempdir()?;
+        let path = parent.path().join("out.csv").to_string_lossy().into_owned();
+        let output = run_once_on(database.clone(), OneShot::ExportCsv(Some(path.clone()))).await?;
+        assert!(output.starts_with("Wrote 1 invitees to "), "{}", output);
+        assert!(std::fs::read_to_string(&path)?.contains("Alice"));
+
+        let output = run_once_on(database, OneShot::Stats).await?;
+        assert!(output.contains("\n1 | 0 | 0 | 1 | 0\n"), "{}", output);
+        Ok(())
+    }
+
+    #[test]
+    fn list_options() -> Result<()> {
+        assert_eq!((DEFAULT_PAGE_SIZE, InviteSort::Id), parse_list_options("")?);
+        assert_eq!((5, InviteSort::RsvpStatus), parse_list_options("5 rsvp")?);
+        assert_eq!((5, InviteSort::Name), parse_list_options(" name  5")?);
+        assert!(parse_list_options("0").is_err());
+        assert!(parse_list_options("5 age").is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn list_invalid_options() -> Result<()> {
+        let output = run_script("list-invites ten\n").await?;
+        assert!(output.contains("Usage: list-invites [page size] [id|name|rsvp]\n"), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unknown_command() -> Result<()> {
+        let output = run_script("paddle\n").await?;
+        assert!(output.contains(&format!("Unknown command paddle. Available commands: {}\n", COMMANDS)), "{}", output);
+        // Each prompt ends its line, and the CLI stops once input runs out
+        assert_eq!(2, output.matches("Enter command:").count());
+        assert!(output.lines().all(|line| !line.contains("quitUnknown")), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn commands_match_whole_words() -> Result<()> {
+        let output = run_script("notebook\nlist-invitesfoo\n").await?;
+        assert!(output.contains("Unknown command notebook."), "{}", output);
+        assert!(output.contains("Unknown command list-invitesfoo."), "{}", output);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn page_beyond_largest_offset() -> Result<()> {
+        let database = in_memory_database().await?;
+        database.insert_invite(DEFAULT_EVENT_ID, "Alice", None, None).await?;
+        let output = run_script_on(database, &format!("list-invites {}\nn\n\n", u32::MAX)).await?;
+        assert!(output.contains("| Alice |"), "{}", output);
+        assert!(output.contains("No more invitees\n"), "{}", output);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expiry_units() {
+        assert_eq!(Some(Duration::from_secs(7 * 24 * 60 * 60)), parse_expiry("7d"));
+        assert_eq!(Some(Duration::from_secs(12 * 60 * 60)), parse_expiry("12h"));
+        assert_eq!(Some(Duration::from_secs(30 * 60)), parse_expiry("30m"));
+    }
+
+    #[test]
+    fn parse_invalid_expiry() {
+        for expiry in ["7", "d", "7w", "-1d", "seven days", "99999999999999999d"] {
+            assert_eq!(None, parse_expiry(expiry), "{}", expiry);
+        }
+    }
+
+    #[test]
+    fn rsvp_time() -> Result<()> {
+        // 2022-09-02 03:30:05 UTC
+        let at_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_405);
+        assert_eq!("02/09/2022 03:30:05", format_time(at_time, UtcOffset::UTC)?);
+        assert_eq!("01/09/2022 23:30:05", format_time(at_time, UtcOffset::from_hms(-4, 0, 0)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn set_and_clear_note() {
+        assert_eq!(Some("Needs a ride"), parse_note("  Needs a ride\n"));
+        assert_eq!(None, parse_note("\n"));
+        assert_eq!(None, parse_note(""));
+    }
+
+    #[test]
+    fn list_note() -> Result<()> {
+        let mut invitee = Invitee {
+            id: InviteId(4),
+            first_name: String::from("Alice"),
+            source: Some(String::from("text")),
+            admin_note: Some(String::from("Knows the river well")),
+            created_at: None,
+            rsvp: None
+        };
+        assert_eq!("4 | Alice | text | Knows the river well | -", invitee_columns(&invitee, UtcOffset::UTC)?);
+        invitee.admin_note = None;
+        assert_eq!("4 | Alice | text | - | -", invitee_columns(&invitee, UtcOffset::UTC)?);
+        Ok(())
+    }
+
+    #[test]
+    fn list_creation_time() -> Result<()> {
+        let invitee = Invitee {
+            id: InviteId(4),
+            first_name: String::from("Alice"),
+            source: None,
+            admin_note: None,
+            created_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_405)),
+            rsvp: None
+        };
+        assert_eq!("4 | Alice | - | - | 02/09/2022 03:30:05", invitee_columns(&invitee, UtcOffset::UTC)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Counts the open connections of each client IP address
+#[derive(Clone)]
+pub struct ConnectionTracker {
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    max_per_ip: usize,
+    /// Trusted proxies, whose connections carry many clients and so are not limited
+    unlimited: Arc<Vec<IpAddr>>
+}
+
+impl ConnectionTracker {
+    pub fn new(max_per_ip: usize, unlimited: Vec<IpAddr>) -> Self {
+        Self {
+            open: Arc::new(Mutex::new(HashMap::new())),
+            max_per_ip,
+            unlimited: Arc::new(unlimited)
+        }
+    }
+
+    /// Registers a new connection, unless the IP address already has too many open.
+    /// The connection counts as open until the returned guard is dropped.
+    pub fn try_open(&self, ip: IpAddr) -> Option<OpenConnection> {
+        if self.unlimited.contains(&ip) {
+            // Never counted, so dropping the guard leaves nothing to forget
+            return Some(OpenConnection {
+                tracker: self.clone(),
+                ip
+            });
+        }
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(ip).or_insert(0);
+        if *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(OpenConnection {
+            tracker: self.clone(),
+            ip
+        })
+    }
+
+    #[cfg(test)]
+    fn open_count(&self, ip: IpAddr) -> usize {
+        self.open.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+pub struct OpenConnection {
+    tracker: ConnectionTracker,
+    ip: IpAddr
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        let mut open = self.tracker.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn limit_connections_per_ip() {
+        let tracker = ConnectionTracker::new(2, Vec::new());
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let other_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+        let first = tracker.try_open(ip).unwrap();
+        let _second = tracker.try_open(ip).unwrap();
+        assert!(tracker.try_open(ip).is_none());
+        assert_eq!(2, tracker.open_count(ip));
+
+        let _other = tracker.try_open(other_ip).unwrap();
+
+        drop(first);
+        assert_eq!(1, tracker.open_count(ip));
+        let _third = tracker.try_open(ip).unwrap();
+    }
+
+    #[test]
+    fn forget_closed_ips() {
+        let tracker = ConnectionTracker::new(1, Vec::new());
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        drop(tracker.try_open(ip).unwrap());
+        assert!(tracker.open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn proxies_not_limited() {
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let tracker = ConnectionTracker::new(1, vec![proxy]);
+
+        let through_proxy: Vec<OpenConnection> = (0..3).map(|_| tracker.try_open(proxy).unwrap()).collect();
+        assert_eq!(0, tracker.open_count(proxy));
+        let _client = tracker.try_open(client).unwrap();
+        assert!(tracker.try_open(client).is_none());
+
+        drop(through_proxy);
+        assert!(tracker.try_open(client).is_none());
+    }
+}
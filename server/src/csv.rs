@@ -0,0 +1,263 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::fmt::{Display, Formatter};
+use eyre::Result;
+use time::{OffsetDateTime, UtcOffset};
+use time::format_description::well_known::Rfc3339;
+use thebestofcmu_common::Invitee;
+
+const INVITEE_HEADER: [&str; 7] = [
+    "id", "first_name", "rsvp_time", "phone_number", "email_address", "attending", "guest_count"
+];
+
+/// Renders every invitee as RFC 4180 CSV, with a header row. The RSVP columns of
+/// invitees who have not RSVPed are left blank.
+pub fn render_invitees(invitees: &[Invitee], utc_offset: UtcOffset) -> Result<String> {
+    let mut csv = String::new();
+    write_record(&mut csv, &INVITEE_HEADER);
+    for invitee in invitees {
+        let id = invitee.id.to_string();
+        let rsvp_columns = match &invitee.rsvp {
+            Some((details, at_time)) => {
+                let at_time: OffsetDateTime = (*at_time).into();
+                [
+                    at_time.to_offset(utc_offset).format(&Rfc3339)?,
+                    details.phone_number.clone().unwrap_or_default(),
+                    details.email_address.clone().unwrap_or_default(),
+                    details.attending.to_string(),
+                    details.guest_count.to_string()
+                ]
+            },
+            None => Default::default()
+        };
+        let mut record = vec![id.as_str(), invitee.first_name.as_str()];
+        record.extend(rsvp_columns.iter().map(String::as_str));
+        write_record(&mut csv, &record);
+    }
+    Ok(csv)
+}
+
+/// Appends a record, quoting the fields which need it, followed by CRLF
+pub fn write_record(csv: &mut String, fields: &[&str]) {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            csv.push(',');
+        }
+        if field.contains(&[',', '"', '\r', '\n'][..]) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push_str("\r\n");
+}
+
+/// A CSV file which could not be read, and the line of the offending record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsvError {
+    pub line: usize,
+    pub reason: &'static str
+}
+
+impl Display for CsvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+/// Parses RFC 4180 CSV into records, each with the line it starts on. Lines may
+/// end in CRLF or LF, and quoted fields may span several lines.
+pub fn parse_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut record_line = 1;
+    // Whether the field was quoted, and whether its closing quote is yet to come
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                },
+                _ => field.push(c)
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            },
+            '"' => return Err(CsvError { line, reason: "quote inside an unquoted field" }),
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                quoted = false;
+            },
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut fields)));
+                quoted = false;
+                line += 1;
+                record_line = line;
+            },
+            _ if quoted => return Err(CsvError { line, reason: "text after a closing quote" }),
+            _ => field.push(c)
+        }
+    }
+    if in_quotes {
+        return Err(CsvError { line: record_line, reason: "quoted field never closed" });
+    }
+    if quoted || !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((record_line, fields));
+    }
+    Ok(records)
+}
+
+/// Reads a list of first names, one per row, skipping blank rows and any header row
+pub fn parse_first_names(text: &str) -> Result<Vec<String>, CsvError> {
+    let mut first_names = Vec::new();
+    for (line, record) in parse_records(text)? {
+        let first_name = match record.as_slice() {
+            [first_name] => first_name.trim(),
+            _ => return Err(CsvError { line, reason: "expected only a first name" })
+        };
+        if first_name.is_empty() {
+            continue;
+        }
+        let is_header = first_names.is_empty() &&
+            ["first_name", "first name", "name"].iter().any(|header| first_name.eq_ignore_ascii_case(header));
+        if !is_header {
+            first_names.push(first_name.to_string());
+        }
+    }
+    Ok(first_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use thebestofcmu_common::{InviteId, RsvpDetails};
+
+    fn invitee(id: i32, first_name: &str) -> Invitee {
+        Invitee {
+            id: InviteId(id),
+            first_name: String::from(first_name),
+            source: None,
+            admin_note: None,
+            created_at: None,
+            rsvp: None
+        }
+    }
+
+    #[test]
+    fn escape_fields() {
+        let mut csv = String::new();
+        write_record(&mut csv, &["plain", "with, comma", "say \"hi\"", "two\nlines", ""]);
+        assert_eq!("plain,\"with, comma\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n", csv);
+    }
+
+    #[test]
+    fn invitees_with_and_without_rsvp() -> Result<()> {
+        let mut alice = invitee(1, "Alice");
+        alice.rsvp = Some((
+            RsvpDetails {
+                phone_number: Some(String::from("+14125550123")),
+                email_address: Some(String::from("alice@example.com")),
+                attending: true,
+                guest_count: 2
+            },
+            // 2022-09-02 03:30:05 UTC
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_405)
+        ));
+        let mut bob = invitee(2, "Bob, Jr.");
+        bob.rsvp = Some((
+            RsvpDetails { phone_number: None, email_address: None, attending: false, guest_count: 1 },
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_662_089_405)
+        ));
+        let csv = render_invitees(&[alice, bob, invitee(3, "Carol \"CJ\"")], UtcOffset::from_hms(-4, 0, 0)?)?;
+        assert_eq!(concat!(
+            "id,first_name,rsvp_time,phone_number,email_address,attending,guest_count\r\n",
+            "1,Alice,2022-09-01T23:30:05-04:00,+14125550123,alice@example.com,true,2\r\n",
+            "2,\"Bob, Jr.\",2022-09-01T23:30:05-04:00,,,false,1\r\n",
+            "3,\"Carol \"\"CJ\"\"\",,,,,\r\n"
+        ), csv);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_quoted_records() -> Result<()> {
+        let records = parse_records("plain,\"with, comma\"\r\n\"say \"\"hi\"\"\",\"two\nlines\"\nlast")?;
+        assert_eq!(vec![
+            (1, vec![String::from("plain"), String::from("with, comma")]),
+            (2, vec![String::from("say \"hi\""), String::from("two\nlines")]),
+            (4, vec![String::from("last")])
+        ], records);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_records() -> Result<()> {
+        let fields = ["plain", "with, comma", "say \"hi\"", "two\nlines", ""];
+        let mut csv = String::new();
+        write_record(&mut csv, &fields);
+        write_record(&mut csv, &fields[..1]);
+        let fields: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+        assert_eq!(vec![(1, fields), (3, vec![String::from("plain")])], parse_records(&csv)?);
+        Ok(())
+    }
+
+    #[test]
+    fn first_names_with_header_and_blank_lines() -> Result<()> {
+        let first_names = parse_first_names("First Name\r\nAlice\r\n\r\n  Bob \r\n\"Carol\"\r\n")?;
+        assert_eq!(vec!["Alice", "Bob", "Carol"], first_names);
+        assert_eq!(vec!["Alice", "Bob"], parse_first_names("Alice\nBob")?);
+        Ok(())
+    }
+
+    #[test]
+    fn first_names_report_line() {
+        assert_eq!(Err(CsvError { line: 3, reason: "expected only a first name" }),
+                   parse_first_names("Alice\nBob\nCarol,Dave\n"));
+        assert_eq!(Err(CsvError { line: 2, reason: "quoted field never closed" }),
+                   parse_first_names("Alice\n\"Bob\nCarol\n"));
+        assert_eq!(Err(CsvError { line: 2, reason: "quote inside an unquoted field" }),
+                   parse_first_names("Alice\nB\"ob\n"));
+        assert_eq!(Err(CsvError { line: 1, reason: "text after a closing quote" }),
+                   parse_first_names("\"Alice\" Smith\n"));
+    }
+}
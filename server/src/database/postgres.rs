@@ -0,0 +1,104 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use eyre::Result;
+use sqlx::{query, PgPool, Postgres, Transaction};
+use sqlx::migrate::Migrator;
+use crate::deadline::Deadline;
+use super::PoolSettings;
+
+/// Postgres, whose reads may go to a replica
+pub struct PostgresBackend {
+    pub pool: PgPool,
+    /// Serves read-only queries, sparing the primary for RSVPs. Unset reads from the primary.
+    pub replica: Option<PgPool>,
+    /// Whether invitees have distinct first names. If not, RSVPs must carry an invite ID.
+    pub unique_names: bool
+}
+
+impl PostgresBackend {
+    pub fn connect_lazy(url: &str, replica_url: Option<&str>, unique_names: bool, pool: PoolSettings) -> Result<Self> {
+        Ok(Self {
+            pool: pool.options().connect_lazy(url)?,
+            replica: match replica_url {
+                Some(replica_url) => Some(pool.options().connect_lazy(replica_url)?),
+                None => None
+            },
+            unique_names
+        })
+    }
+
+    pub(super) fn sql(sql: &str) -> String {
+        super::numbered_placeholders(sql)
+    }
+
+    pub(super) const UNIQUE_VIOLATION: &'static str = "23505";
+
+    pub(super) const NAME_CONTAINS: &'static str = r#""invited"."first_name" ILIKE ?"#;
+
+    pub(super) fn read_pool(&self) -> &PgPool {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    pub(super) fn write_pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    // Has Postgres abandon the statements too, should the request run out of time
+    pub(super) async fn limit_statements(transaction: &mut Transaction<'_, Postgres>, deadline: Deadline) -> Result<()> {
+        let statement_timeout = deadline.remaining().as_millis().max(1);
+        query(&format!("SET LOCAL statement_timeout = {}", statement_timeout))
+            .execute(transaction)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) const MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+
+    // Which names must be unique is configured rather than migrated, so it may change between runs
+    pub(super) async fn constrain_names(&self) -> Result<()> {
+        let mut connection = self.write_pool().acquire().await?;
+        if self.unique_names {
+            query(r#"
+            CREATE UNIQUE INDEX IF NOT EXISTS "event_normalized_name_uniqueness" ON "invited" ("event_id", lower("first_name"));
+            "#).execute(&mut connection).await?;
+        } else {
+            query(r#"
+            DROP INDEX IF EXISTS "event_normalized_name_uniqueness";
+            "#).execute(&mut connection).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn reads_from_replica() -> Result<()> {
+        let mut backend = PostgresBackend::connect_lazy("postgres://primary/thebestofcmu", None, true, PoolSettings::default())?;
+        assert!(std::ptr::eq(&backend.pool, backend.read_pool()));
+
+        backend.replica = Some(PgPool::connect_lazy("postgres://replica/thebestofcmu")?);
+        assert!(std::ptr::eq(backend.replica.as_ref().unwrap(), backend.read_pool()));
+        assert!(std::ptr::eq(&backend.pool, backend.write_pool()));
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use async_std::task;
+use async_trait::async_trait;
+use eyre::Result;
+use time::{Date, UtcOffset};
+use thebestofcmu_common::{ClientCancellation, ClientRSVP, InviteId, Invitee, RsvpStatus, ServerResponse};
+use crate::deadline::Deadline;
+use crate::event::Event;
+use crate::fsck::{Fix, StoredInvitee};
+use super::{Backend, ConnectionLost, InviteOutcome, InviteSort, RsvpSummary, SourceStats};
+
+// Doubled before each further retry
+const FIRST_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Whether the error may well not recur, as when a connection drops. Errors the
+/// database itself raised, such as constraint violations, would only recur.
+fn is_transient(error: &eyre::Report) -> bool {
+    if error.downcast_ref::<ConnectionLost>().is_some() {
+        return true;
+    }
+    matches!(error.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed))
+}
+
+/// Whether the error came before the database could have been reached, as when no connection
+/// was free. Other transient errors, such as a dropped connection, may come after a write was
+/// committed, so only these make it safe to repeat a write which is not idempotent.
+fn is_unsent(error: &eyre::Report) -> bool {
+    matches!(error.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::PoolTimedOut))
+}
+
+// Makes the attempt, repeating it up to the given number of times should it fail with an error
+// which is retryable. Retrying stops early rather than wait out the deadline.
+async fn with_retries<T, F, A>(retries: u32,
+                               deadline: Option<Deadline>,
+                               retryable: fn(&eyre::Report) -> bool,
+                               mut attempt: F) -> Result<T>
+    where F: FnMut() -> A,
+          A: Future<Output=Result<T>> {
+
+    let mut backoff = FIRST_BACKOFF;
+    let mut retries_left = retries;
+    loop {
+        match attempt().await {
+            Err(e) if retries_left > 0 && retryable(&e) => {
+                if deadline.map(|deadline| deadline.remaining() <= backoff).unwrap_or(false) {
+                    return Err(e);
+                }
+                log::warn!("Retrying in {:?} after database error: {}", backoff, e);
+                task::sleep(backoff).await;
+                backoff *= 2;
+                retries_left -= 1;
+            },
+            result => return result
+        }
+    }
+}
+
+// Makes the RSVP, repeating it after any transient error. That is safe because an RSVP is
+// recorded at most once per invitee: should the connection drop after an attempt was
+// committed, the next attempt finds that RSVP. It is looked up, and if it has the same details
+// and time as this one, the earlier attempt recorded it, so the RSVP succeeded.
+async fn insert_rsvp_with_retries<F, A, L, B>(retries: u32,
+                                              deadline: Deadline,
+                                              rsvp: &ClientRSVP,
+                                              time_since_epoch: u64,
+                                              mut attempt: F,
+                                              look_up: L) -> Result<ServerResponse>
+    where F: FnMut() -> A,
+          A: Future<Output=Result<ServerResponse>>,
+          L: FnOnce() -> B,
+          B: Future<Output=Result<RsvpStatus>> {
+
+    let mut attempts = 0;
+    let response = with_retries(retries, Some(deadline), is_transient, || {
+        attempts += 1;
+        attempt()
+    }).await?;
+    match response {
+        ServerResponse::AlreadyRSVPed(time_registered) if attempts > 1 && time_registered == time_since_epoch => {
+            let ours = RsvpStatus::RSVPed(rsvp.details.clone(), time_since_epoch);
+            Ok(if look_up().await? == ours { ServerResponse::Success } else { response })
+        },
+        response => Ok(response)
+    }
+}
+
+/// Retries calls to the backend which fail transiently, waiting longer before each retry.
+/// Reads, and writes which come out the same when repeated, are retried after any transient
+/// error, as are RSVPs, whose repeats are recognized. Other writes are retried only if they
+/// never reached the database, since a connection may drop after the write was committed,
+/// and repeating it would then report, say, an invitee as already invited.
+pub struct RetryingBackend {
+    pub inner: Arc<dyn Backend>,
+    pub retries: u32
+}
+
+#[async_trait]
+impl Backend for RetryingBackend {
+    async fn ping(&self) -> Result<()> {
+        with_retries(self.retries, None, is_transient, || self.inner.ping()).await
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        with_retries(self.retries, None, is_transient, || self.inner.migrate()).await
+    }
+
+    async fn drop_schema(&self) -> Result<()> {
+        with_retries(self.retries, None, is_transient, || self.inner.drop_schema()).await
+    }
+
+    async fn insert_event(&self, slug: &str, title_html: Option<&str>, details_html: Option<&str>) -> Result<i32> {
+        with_retries(self.retries, None, is_unsent, || self.inner.insert_event(slug, title_html, details_html)).await
+    }
+
+    async fn select_event(&self, slug: &str) -> Result<Option<Event>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_event(slug)).await
+    }
+
+    async fn insert_invite(&self,
+                           event_id: i32,
+                           first_name: &str,
+                           source: Option<&str>,
+                           expires_at: Option<SystemTime>) -> Result<InviteOutcome> {
+        with_retries(self.retries, None, is_unsent, || self.inner.insert_invite(event_id, first_name, source, expires_at)).await
+    }
+
+    async fn insert_invites(&self, event_id: i32, first_names: &[String], source: Option<&str>) -> Result<InviteOutcome> {
+        with_retries(self.retries, None, is_unsent, || self.inner.insert_invites(event_id, first_names, source)).await
+    }
+
+    async fn delete_invite(&self, id: InviteId) -> Result<bool> {
+        with_retries(self.retries, None, is_unsent, || self.inner.delete_invite(id)).await
+    }
+
+    async fn update_admin_note(&self, id: InviteId, admin_note: Option<&str>) -> Result<bool> {
+        with_retries(self.retries, None, is_transient, || self.inner.update_admin_note(id, admin_note)).await
+    }
+
+    async fn select_invites(&self, event_id: i32) -> Result<Vec<Invitee>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_invites(event_id)).await
+    }
+
+    async fn select_invites_page(&self, event_id: i32, limit: u32, offset: u32, sort: InviteSort) -> Result<Vec<Invitee>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_invites_page(event_id, limit, offset, sort)).await
+    }
+
+    async fn search_invites_by_name(&self, event_id: i32, name: &str) -> Result<Vec<Invitee>> {
+        with_retries(self.retries, None, is_transient, || self.inner.search_invites_by_name(event_id, name)).await
+    }
+
+    async fn select_stats_by_source(&self, event_id: i32) -> Result<Vec<SourceStats>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_stats_by_source(event_id)).await
+    }
+
+    async fn select_rsvp_summary(&self, event_id: i32) -> Result<RsvpSummary> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_rsvp_summary(event_id)).await
+    }
+
+    async fn select_rsvp_days(&self, event_id: i32, utc_offset: UtcOffset) -> Result<BTreeMap<Date, usize>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_rsvp_days(event_id, utc_offset)).await
+    }
+
+    async fn select_stored_invitees(&self) -> Result<Vec<StoredInvitee>> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_stored_invitees()).await
+    }
+
+    async fn apply_fixes(&self, fixes: &[Fix]) -> Result<()> {
+        with_retries(self.retries, None, is_transient, || self.inner.apply_fixes(fixes)).await
+    }
+
+    async fn select_rsvp_by_name(&self,
+                                 event_id: i32,
+                                 first_name: &str,
+                                 invite_id: Option<InviteId>) -> Result<RsvpStatus> {
+        with_retries(self.retries, None, is_transient, || self.inner.select_rsvp_by_name(event_id, first_name, invite_id)).await
+    }
+
+    async fn delete_rsvp(&self,
+                         cancellation: ClientCancellation,
+                         event_id: i32,
+                         deadline: Deadline) -> Result<ServerResponse> {
+        with_retries(self.retries, Some(deadline), is_unsent, || {
+            self.inner.delete_rsvp(cancellation.clone(), event_id, deadline)
+        }).await
+    }
+
+    async fn insert_rsvp_at(&self,
+                            rsvp: ClientRSVP,
+                            event_id: i32,
+                            time_since_epoch: u64,
+                            deadline: Deadline) -> Result<ServerResponse> {
+        insert_rsvp_with_retries(self.retries, deadline, &rsvp, time_since_epoch, || {
+            self.inner.insert_rsvp_at(rsvp.clone(), event_id, time_since_epoch, deadline)
+        }, || {
+            self.inner.select_rsvp_by_name(event_id, &rsvp.first_name, rsvp.invite_id)
+        }).await
+    }
+
+    async fn update_rsvp_at(&self,
+                            rsvp: ClientRSVP,
+                            event_id: i32,
+                            time_since_epoch: u64,
+                            deadline: Deadline) -> Result<ServerResponse> {
+        with_retries(self.retries, Some(deadline), is_transient, || {
+            self.inner.update_rsvp_at(rsvp.clone(), event_id, time_since_epoch, deadline)
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug)]
+    struct UniqueViolation;
+
+    impl std::fmt::Display for UniqueViolation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("duplicate key value violates unique constraint")
+        }
+    }
+
+    impl std::error::Error for UniqueViolation {}
+
+    impl sqlx::error::DatabaseError for UniqueViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed("23505"))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn connection_reset() -> eyre::Report {
+        sqlx::Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset)).into()
+    }
+
+    // Fails with the errors in turn, then succeeds, counting every attempt
+    fn failing_executor(errors: Vec<fn() -> eyre::Report>) -> (Arc<AtomicU32>, impl FnMut() -> std::future::Ready<Result<u32>>) {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        (attempts, move || {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            std::future::ready(match errors.get(attempt as usize) {
+                Some(error) => Err(error()),
+                None => Ok(attempt)
+            })
+        })
+    }
+
+    #[async_std::test]
+    async fn transient_errors_retried() -> Result<()> {
+        let (attempts, executor) = failing_executor(vec![connection_reset, || sqlx::Error::PoolTimedOut.into()]);
+        assert_eq!(2, with_retries(2, None, is_transient, executor).await?);
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retries_run_out() {
+        let (attempts, executor) = failing_executor(vec![connection_reset; 4]);
+        let error = with_retries(2, None, is_transient, executor).await.unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn constraint_violations_surface_immediately() {
+        let (attempts, executor) = failing_executor(vec![|| sqlx::Error::Database(Box::new(UniqueViolation)).into()]);
+        let error = with_retries(2, None, is_transient, executor).await.unwrap_err();
+        assert!(!is_transient(&error));
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn no_retry_past_deadline() {
+        let (attempts, executor) = failing_executor(vec![connection_reset]);
+        let deadline = Deadline::after(FIRST_BACKOFF / 2);
+        assert!(with_retries(2, Some(deadline), is_transient, executor).await.is_err());
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn writes_retried_only_if_unsent() -> Result<()> {
+        // The connection may have dropped after the write was committed
+        let (attempts, executor) = failing_executor(vec![connection_reset]);
+        assert!(with_retries(2, None, is_unsent, executor).await.is_err());
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+
+        let (attempts, executor) = failing_executor(vec![|| sqlx::Error::PoolTimedOut.into()]);
+        assert_eq!(1, with_retries(2, None, is_unsent, executor).await?);
+        assert_eq!(2, attempts.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    fn rsvp() -> ClientRSVP {
+        ClientRSVP {
+            first_name: String::from("Alice"),
+            invite_id: None,
+            details: thebestofcmu_common::RsvpDetails {
+                phone_number: None,
+                email_address: Some(String::from("alice@example.com")),
+                attending: true,
+                guest_count: 2
+            }
+        }
+    }
+
+    // The first attempt is committed, but its connection drops before the commit is answered
+    async fn insert_rsvp_after_reset(recorded: RsvpStatus) -> Result<(u32, ServerResponse)> {
+        const TIME: u64 = 1_662_089_400;
+        let (attempts, mut executor) = failing_executor(vec![connection_reset]);
+        let attempt = || {
+            let result = executor();
+            async move { result.await.map(|_| ServerResponse::AlreadyRSVPed(TIME)) }
+        };
+        let deadline = Deadline::after(Duration::from_secs(30));
+        let response = insert_rsvp_with_retries(2, deadline, &rsvp(), TIME, attempt, || async { Ok(recorded) }).await?;
+        Ok((attempts.load(Ordering::SeqCst), response))
+    }
+
+    #[async_std::test]
+    async fn rsvp_retried_after_dropped_connection() -> Result<()> {
+        let recorded = RsvpStatus::RSVPed(rsvp().details, 1_662_089_400);
+        assert_eq!((2, ServerResponse::Success), insert_rsvp_after_reset(recorded).await?);
+
+        // Someone else RSVPed for the invitee in the same second
+        let mut details = rsvp().details;
+        details.attending = false;
+        let recorded = RsvpStatus::RSVPed(details, 1_662_089_400);
+        assert_eq!((2, ServerResponse::AlreadyRSVPed(1_662_089_400)), insert_rsvp_after_reset(recorded).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn earlier_rsvp_not_mistaken_for_retry() -> Result<()> {
+        let (_, mut executor) = failing_executor(Vec::new());
+        let attempt = || {
+            let result = executor();
+            async move { result.await.map(|_| ServerResponse::AlreadyRSVPed(1_662_089_400)) }
+        };
+        let deadline = Deadline::after(Duration::from_secs(30));
+        let response = insert_rsvp_with_retries(2, deadline, &rsvp(), 1_662_089_400, attempt, || async {
+            Ok(RsvpStatus::RSVPed(rsvp().details, 1_662_089_400))
+        }).await?;
+        assert_eq!(ServerResponse::AlreadyRSVPed(1_662_089_400), response);
+        Ok(())
+    }
+
+    #[test]
+    fn connection_lost_is_transient() {
+        let error = eyre::Report::new(ConnectionLost { rows_read: 3, source: sqlx::Error::WorkerCrashed });
+        assert!(is_transient(&error));
+        assert!(!is_transient(&eyre::eyre!("Invalid phone number")));
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::str::FromStr;
+use eyre::Result;
+use sqlx::{query, Sqlite, SqlitePool, Transaction};
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqliteConnectOptions;
+use crate::deadline::Deadline;
+use super::PoolSettings;
+
+/// A SQLite file, or an in-memory database, for running without a Postgres server
+pub struct SqliteBackend {
+    pub pool: SqlitePool,
+    /// Whether invitees have distinct first names. If not, RSVPs must carry an invite ID.
+    pub unique_names: bool
+}
+
+impl SqliteBackend {
+    pub fn connect_lazy(url: &str, unique_names: bool, pool: PoolSettings) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = if url.contains(":memory:") || url.contains("mode=memory") {
+            // An in-memory database vanishes with its last connection, and its
+            // connections would lock each other out of its shared cache
+            pool.options()
+                .min_connections(0)
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_lazy_with(options)
+        } else {
+            pool.options().connect_lazy_with(options)
+        };
+        Ok(Self { pool, unique_names })
+    }
+
+    pub(super) fn sql(sql: &str) -> String {
+        sql.to_string()
+    }
+
+    // SQLITE_CONSTRAINT_UNIQUE, an extended result code
+    pub(super) const UNIQUE_VIOLATION: &'static str = "2067";
+
+    pub(super) const NAME_CONTAINS: &'static str = r#""invited"."first_name" LIKE ? ESCAPE '\'"#;
+
+    pub(super) fn read_pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub(super) fn write_pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    // SQLite has no statement timeout, and its statements never wait on a network
+    pub(super) async fn limit_statements(_transaction: &mut Transaction<'_, Sqlite>, _deadline: Deadline) -> Result<()> {
+        Ok(())
+    }
+
+    pub(super) const MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+    // Which names must be unique is configured rather than migrated, so it may change between runs
+    pub(super) async fn constrain_names(&self) -> Result<()> {
+        let mut connection = self.write_pool().acquire().await?;
+        if self.unique_names {
+            query(r#"
+            CREATE UNIQUE INDEX IF NOT EXISTS "event_normalized_name_uniqueness" ON "invited" ("event_id", lower("first_name"));
+            "#).execute(&mut connection).await?;
+        } else {
+            query(r#"
+            DROP INDEX IF EXISTS "event_normalized_name_uniqueness";
+            "#).execute(&mut connection).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+use async_std::future::{self, TimeoutError};
+
+/// The point in time by which a request must be answered
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant
+}
+
+impl Deadline {
+    pub fn after(budget: Duration) -> Self {
+        Self { at: Instant::now() + budget }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// Drives the future until the deadline. If the deadline passes first, the future
+    /// is dropped, which abandons any database work in progress.
+    pub async fn run<F>(&self, future: F) -> Result<F::Output, TimeoutError>
+        where F: Future {
+
+        future::timeout(self.remaining(), future).await
+    }
+}
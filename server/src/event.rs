@@ -0,0 +1,82 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+/// The event served without an /e/{slug} prefix, created by the first migration
+pub const DEFAULT_EVENT_ID: i32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub id: i32,
+    pub slug: String,
+    /// Trusted HTML for the page heading. Unset uses that of the built-in page.
+    pub title_html: Option<String>,
+    /// Trusted HTML describing the date, place and cost. Unset uses that of the built-in page.
+    pub details_html: Option<String>
+}
+
+/// Splits a request path such as /e/{slug}/enter-rsvp into the event slug and the
+/// path within the event. Paths without the prefix belong to the default event.
+pub fn split_event_path(path: &str) -> (Option<&str>, &str) {
+    let within_events = match path.strip_prefix("/e/") {
+        Some(within_events) => within_events,
+        None => return (None, path)
+    };
+    match within_events.find('/') {
+        Some(0) => (None, path),
+        Some(slash) => (Some(&within_events[..slash]), &within_events[slash..]),
+        // Relative links on the event page need the trailing slash
+        None if !within_events.is_empty() => (Some(within_events), ""),
+        None => (None, path)
+    }
+}
+
+/// Slugs appear in URLs, so they are kept to lowercase letters, digits and dashes
+pub fn valid_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug.len() <= 32 &&
+        slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_of_events() {
+        assert_eq!((Some("fall-trip"), "/enter-rsvp"), split_event_path("/e/fall-trip/enter-rsvp"));
+        assert_eq!((Some("fall-trip"), "/"), split_event_path("/e/fall-trip/"));
+        assert_eq!((Some("fall-trip"), ""), split_event_path("/e/fall-trip"));
+        assert_eq!((Some("fall-trip"), "/kayaking-background.webp"),
+                   split_event_path("/e/fall-trip/kayaking-background.webp"));
+    }
+
+    #[test]
+    fn paths_of_default_event() {
+        for path in ["/", "/enter-rsvp", "/e/", "/e//enter-rsvp", "/events/fall-trip/"] {
+            assert_eq!((None, path), split_event_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn slugs() {
+        assert!(valid_slug("fall-trip-2022"));
+        for slug in ["", "Fall", "fall trip", "fall/trip", "ünïcode", &"a".repeat(33)] {
+            assert!(!valid_slug(slug), "{}", slug);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+
+use std::net::IpAddr;
+use hyper::header;
+use hyper::http::request;
+
+/// The address of the peer which sent a request, if it has one. Peers on a Unix
+/// socket have none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerAddress(pub IpAddr);
+
+/// Where a request really came from, believing the Forwarded header, or else X-Forwarded-For,
+/// X-Forwarded-Proto and X-Forwarded-Host, only when a trusted proxy sent it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardedContext {
+    pub client_ip: Option<IpAddr>,
+    pub https: bool,
+    /// The requested host, including any port
+    pub host: Option<String>
+}
+
+impl ForwardedContext {
+    pub fn extract(request_parts: &request::Parts, serves_https: bool, trusted_proxies: &[IpAddr]) -> Self {
+        let peer_ip = request_parts.extensions.get::<PeerAddress>().map(|peer| peer.0);
+        // HTTP/2 requests carry the host in the URI rather than the Host header
+        let host = match request_parts.headers.get(header::HOST) {
+            Some(host) => host.to_str().ok().map(String::from),
+            None => request_parts.uri.host().map(String::from)
+        };
+        let direct = Self { client_ip: peer_ip, https: serves_https, host };
+        match peer_ip {
+            Some(peer_ip) if trusted_proxies.contains(&peer_ip) && request_parts.headers.contains_key(header::FORWARDED) => {
+                let elements: Vec<ForwardedElement> = request_parts.headers.get_all(header::FORWARDED)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .map(ForwardedElement::parse)
+                    .collect();
+                let chain: Vec<Option<IpAddr>> = elements.iter().map(|element| element.client_ip).collect();
+                // The proxy which received the request from the client says how it did so.
+                // Entries to the left of its own were written by the client.
+                let hop = client_hop(&chain, trusted_proxies).and_then(|hop| elements.iter().rev().nth(hop));
+                Self {
+                    client_ip: hop.and_then(|hop| hop.client_ip).or(direct.client_ip),
+                    https: hop.and_then(|hop| hop.proto.as_deref())
+                        .map(|proto| proto.eq_ignore_ascii_case("https"))
+                        .unwrap_or(direct.https),
+                    host: hop.and_then(|hop| hop.host.clone()).or(direct.host)
+                }
+            },
+            Some(peer_ip) if trusted_proxies.contains(&peer_ip) => {
+                let header = |name: &str| request_parts.headers.get(name)
+                    .and_then(|value| value.to_str().ok());
+                let list = |name: &str| -> Vec<&str> {
+                    header(name).map(|value| value.split(',').map(str::trim).collect()).unwrap_or_default()
+                };
+                let chain: Vec<Option<IpAddr>> = list("X-Forwarded-For").iter()
+                    .map(|address| address.parse().ok())
+                    .collect();
+                let hop = client_hop(&chain, trusted_proxies);
+                // Where every proxy appended to the list, as their lengths show, the entry as far
+                // from the right as the client's address is the one for the client's hop. Otherwise
+                // only the rightmost entry, which our nearest proxy wrote or passed on, is believed.
+                // Entries further left may have been written by the client.
+                let at_hop = |name: &str| {
+                    let values = list(name);
+                    let from_right = if values.len() == chain.len() { hop? } else { 0 };
+                    values.into_iter().rev().nth(from_right).filter(|value| !value.is_empty())
+                };
+                Self {
+                    client_ip: hop.and_then(|hop| chain.iter().rev().nth(hop).copied().flatten())
+                        .or(direct.client_ip),
+                    https: at_hop("X-Forwarded-Proto")
+                        .map(|proto| proto.eq_ignore_ascii_case("https"))
+                        .unwrap_or(direct.https),
+                    host: at_hop("X-Forwarded-Host").map(String::from).or(direct.host)
+                }
+            },
+            _ => direct
+        }
+    }
+}
+
+// Each proxy appends the address it received the request from, so the client is
+// the rightmost address which is not one of our proxies. Yields how many entries
+// from the right the client is, giving up at any address which could not be read.
+fn client_hop(chain: &[Option<IpAddr>], trusted_proxies: &[IpAddr]) -> Option<usize> {
+    let mut hop = None;
+    for (from_right, address) in chain.iter().rev().enumerate() {
+        let address = (*address)?;
+        hop = Some(from_right);
+        if !trusted_proxies.contains(&address) {
+            break;
+        }
+    }
+    hop
+}
+
+/// One proxy's entry in an RFC 7239 Forwarded header, such as
+/// for="[2001:db8::17]:4711";proto=https;host=thebestofcmu.example
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct ForwardedElement {
+    /// Unset for obfuscated identifiers and "unknown"
+    client_ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>
+}
+
+impl ForwardedElement {
+    fn parse(element: &str) -> Self {
+        let mut parsed = Self::default();
+        for pair in element.split(';') {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                None => continue
+            };
+            if name.eq_ignore_ascii_case("for") {
+                parsed.client_ip = parse_node(value);
+            } else if name.eq_ignore_ascii_case("proto") {
+                parsed.proto = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("host") {
+                parsed.host = Some(value.to_string());
+            }
+        }
+        parsed
+    }
+}
+
+// A node is an address with an optional port, IPv6 addresses being bracketed
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split(']').next()?.parse().ok();
+    }
+    let address = node.split(':').next()?;
+    address.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, Request};
+
+    const PROXY: [u8; 4] = [10, 0, 0, 2];
+
+    fn parts(peer: [u8; 4], headers: &[(&str, &str)]) -> request::Parts {
+        let mut request = Request::get("/").header(header::HOST, "internal:8080");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let (mut parts, _) = request.body(Body::empty()).unwrap().into_parts();
+        parts.extensions.insert(PeerAddress(IpAddr::from(peer)));
+        parts
+    }
+
+    fn forwarded_headers() -> [(&'static str, &'static str); 3] {
+        [
+            ("X-Forwarded-For", "203.0.113.7, 10.0.0.3"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "thebestofcmu.example")
+        ]
+    }
+
+    #[test]
+    fn trusted_proxy() {
+        let trusted = [IpAddr::from(PROXY), IpAddr::from([10, 0, 0, 3])];
+        let context = ForwardedContext::extract(&parts(PROXY, &forwarded_headers()), false, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            https: true,
+            host: Some(String::from("thebestofcmu.example"))
+        }, context);
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let trusted = [IpAddr::from(PROXY)];
+        let context = ForwardedContext::extract(&parts([198, 51, 100, 4], &forwarded_headers()), false, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from([198, 51, 100, 4])),
+            https: false,
+            host: Some(String::from("internal:8080"))
+        }, context);
+    }
+
+    #[test]
+    fn spoofed_chain_stops_at_first_untrusted() {
+        // The client prepended a fake address; only our proxy's view counts
+        let trusted = [IpAddr::from(PROXY)];
+        let headers = [("X-Forwarded-For", "192.0.2.1, 203.0.113.7")];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(Some(IpAddr::from([203, 0, 113, 7])), context.client_ip);
+    }
+
+    #[test]
+    fn trusted_proxy_without_headers() {
+        let trusted = [IpAddr::from(PROXY)];
+        let context = ForwardedContext::extract(&parts(PROXY, &[]), true, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from(PROXY)),
+            https: true,
+            host: Some(String::from("internal:8080"))
+        }, context);
+    }
+
+    #[test]
+    fn forwarded_header_from_trusted_proxy() {
+        let trusted = [IpAddr::from(PROXY), IpAddr::from([10, 0, 0, 3])];
+        let headers = [
+            ("Forwarded", r#"for="203.0.113.7:47011";proto=https;host=thebestofcmu.example, for=10.0.0.3"#),
+            // Ignored in favor of the standard header
+            ("X-Forwarded-For", "198.51.100.9")
+        ];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            https: true,
+            host: Some(String::from("thebestofcmu.example"))
+        }, context);
+    }
+
+    #[test]
+    fn forwarded_header_from_untrusted_peer() {
+        let trusted = [IpAddr::from(PROXY)];
+        let headers = [("Forwarded", "for=203.0.113.7;proto=https")];
+        let context = ForwardedContext::extract(&parts([198, 51, 100, 4], &headers), false, &trusted);
+        assert_eq!(Some(IpAddr::from([198, 51, 100, 4])), context.client_ip);
+        assert!(!context.https);
+    }
+
+    #[test]
+    fn forwarded_header_spoofed_or_obfuscated() {
+        let trusted = [IpAddr::from(PROXY)];
+        let headers = [("Forwarded", "for=192.0.2.1, for=203.0.113.7")];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(Some(IpAddr::from([203, 0, 113, 7])), context.client_ip);
+
+        let headers = [("Forwarded", "for=_hidden")];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(Some(IpAddr::from(PROXY)), context.client_ip);
+    }
+
+    #[test]
+    fn client_cannot_claim_https() {
+        // The client wrote the first entry of each, and our proxy the last
+        let trusted = [IpAddr::from(PROXY)];
+        let headers = [("Forwarded", "for=192.0.2.1;proto=https;host=evil.example, for=203.0.113.7;proto=http;host=thebestofcmu.example")];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            https: false,
+            host: Some(String::from("thebestofcmu.example"))
+        }, context);
+
+        let headers = [
+            ("X-Forwarded-For", "192.0.2.1, 203.0.113.7"),
+            ("X-Forwarded-Proto", "https, http"),
+            ("X-Forwarded-Host", "evil.example, thebestofcmu.example")
+        ];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(ForwardedContext {
+            client_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            https: false,
+            host: Some(String::from("thebestofcmu.example"))
+        }, context);
+
+        // Without a matching X-Forwarded-For entry, the client's claim is not lined up with a hop
+        let headers = [("X-Forwarded-Proto", "https, http")];
+        assert!(!ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted).https);
+    }
+
+    #[test]
+    fn forwarded_nodes() {
+        assert_eq!(Some(IpAddr::from([192, 0, 2, 43])), parse_node("192.0.2.43"));
+        assert_eq!(Some(IpAddr::from([192, 0, 2, 43])), parse_node("192.0.2.43:47011"));
+        assert_eq!("2001:db8:cafe::17".parse().ok(), parse_node("[2001:db8:cafe::17]:4711"));
+        assert_eq!(None, parse_node("unknown"));
+    }
+
+    #[test]
+    fn garbled_chain_ignored() {
+        let trusted = [IpAddr::from(PROXY)];
+        let headers = [("X-Forwarded-For", "not-an-address")];
+        let context = ForwardedContext::extract(&parts(PROXY, &headers), false, &trusted);
+        assert_eq!(Some(IpAddr::from(PROXY)), context.client_ip);
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use thebestofcmu_common::InviteId;
+
+/// An invitee and their RSVP, if any, as stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredInvitee {
+    pub id: InviteId,
+    pub event_id: i32,
+    pub first_name: String,
+    pub expires_at: Option<i64>,
+    pub rsvp: Option<StoredRsvp>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredRsvp {
+    pub phone_number: Option<String>,
+    pub email_address: Option<String>,
+    pub attending: bool,
+    pub time_registered: i64
+}
+
+/// Data which the server would never have written by itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// An acceptance with neither a phone number nor an email address
+    RsvpWithoutContact(InviteId),
+    RsvpAfterExpiry { id: InviteId, expires_at: i64, time_registered: i64 },
+    RsvpInFuture { id: InviteId, time_registered: i64 },
+    /// A first name with surrounding whitespace, which no RSVP can match
+    UntrimmedName { id: InviteId, first_name: String, conflicts: bool }
+}
+
+/// A correction which loses nothing the invitee could not simply enter again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fix {
+    /// Deletes the RSVP, letting the invitee RSVP afresh
+    ClearRsvp(InviteId),
+    TrimName(InviteId, String)
+}
+
+impl Inconsistency {
+    /// The safe correction, if there is one. Otherwise a coordinator must decide.
+    pub fn fix(&self) -> Option<Fix> {
+        match self {
+            Inconsistency::RsvpWithoutContact(id) => Some(Fix::ClearRsvp(*id)),
+            Inconsistency::UntrimmedName { id, first_name, conflicts: false } => {
+                Some(Fix::TrimName(*id, first_name.trim().to_string()))
+            },
+            _ => None
+        }
+    }
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Inconsistency::RsvpWithoutContact(id) => {
+                write!(f, "Invitee {} RSVPed without any contact info", id)
+            },
+            Inconsistency::RsvpAfterExpiry { id, expires_at, time_registered } => {
+                write!(f, "Invitee {} RSVPed at {}, after their invite expired at {}", id, time_registered, expires_at)
+            },
+            Inconsistency::RsvpInFuture { id, time_registered } => {
+                write!(f, "Invitee {} RSVPed at {}, which is in the future", id, time_registered)
+            },
+            Inconsistency::UntrimmedName { id, first_name, conflicts } => {
+                write!(f, "Invitee {} has the untrimmed first name {:?}", id, first_name)?;
+                if *conflicts {
+                    write!(f, ", and another invitee already has the trimmed name")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Fix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fix::ClearRsvp(id) => write!(f, "delete the RSVP of invitee {}", id),
+            Fix::TrimName(id, first_name) => write!(f, "rename invitee {} to {:?}", id, first_name)
+        }
+    }
+}
+
+/// Finds every inconsistency, given the current seconds since the Unix epoch
+pub fn check(invitees: &[StoredInvitee], now: i64) -> Vec<Inconsistency> {
+    let names: HashSet<(i32, &str)> = invitees.iter()
+        .map(|invitee| (invitee.event_id, invitee.first_name.as_str()))
+        .collect();
+    let mut found = Vec::new();
+    for invitee in invitees {
+        let trimmed = invitee.first_name.trim();
+        if trimmed != invitee.first_name {
+            found.push(Inconsistency::UntrimmedName {
+                id: invitee.id,
+                first_name: invitee.first_name.clone(),
+                conflicts: names.contains(&(invitee.event_id, trimmed))
+            });
+        }
+        let rsvp = match &invitee.rsvp {
+            Some(rsvp) => rsvp,
+            None => continue
+        };
+        if rsvp.attending && rsvp.phone_number.is_none() && rsvp.email_address.is_none() {
+            found.push(Inconsistency::RsvpWithoutContact(invitee.id));
+        }
+        match invitee.expires_at {
+            Some(expires_at) if rsvp.time_registered >= expires_at => {
+                found.push(Inconsistency::RsvpAfterExpiry {
+                    id: invitee.id, expires_at, time_registered: rsvp.time_registered
+                });
+            },
+            _ => {}
+        }
+        if rsvp.time_registered > now {
+            found.push(Inconsistency::RsvpInFuture { id: invitee.id, time_registered: rsvp.time_registered });
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::DEFAULT_EVENT_ID;
+
+    const NOW: i64 = 1_662_163_200;
+
+    fn invitee(id: i32, first_name: &str) -> StoredInvitee {
+        StoredInvitee {
+            id: InviteId(id),
+            event_id: DEFAULT_EVENT_ID,
+            first_name: String::from(first_name),
+            expires_at: None,
+            rsvp: None
+        }
+    }
+
+    fn rsvp(phone_number: Option<&str>, time_registered: i64) -> Option<StoredRsvp> {
+        Some(StoredRsvp { phone_number: phone_number.map(String::from), email_address: None, attending: true, time_registered })
+    }
+
+    #[test]
+    fn consistent_data() {
+        let mut with_rsvp = invitee(2, "Bob");
+        with_rsvp.expires_at = Some(NOW);
+        with_rsvp.rsvp = rsvp(Some("+14125550123"), NOW - 1);
+        assert_eq!(Vec::<Inconsistency>::new(), check(&[invitee(1, "Alice"), with_rsvp], NOW));
+    }
+
+    #[test]
+    fn repair_rsvp_without_contact() {
+        let mut fixture = invitee(1, "Alice");
+        fixture.rsvp = rsvp(None, NOW - 60);
+        let found = check(&[fixture], NOW);
+        assert_eq!(vec![Inconsistency::RsvpWithoutContact(InviteId(1))], found);
+        assert_eq!(Some(Fix::ClearRsvp(InviteId(1))), found[0].fix());
+    }
+
+    #[test]
+    fn decline_without_contact() {
+        let mut fixture = invitee(1, "Alice");
+        fixture.rsvp = rsvp(None, NOW - 60);
+        fixture.rsvp.as_mut().unwrap().attending = false;
+        assert_eq!(Vec::<Inconsistency>::new(), check(&[fixture], NOW));
+    }
+
+    #[test]
+    fn report_rsvp_after_expiry() {
+        let mut fixture = invitee(1, "Alice");
+        fixture.expires_at = Some(NOW - 60);
+        fixture.rsvp = rsvp(Some("+14125550123"), NOW - 60);
+        let found = check(&[fixture], NOW);
+        assert_eq!(vec![Inconsistency::RsvpAfterExpiry {
+            id: InviteId(1), expires_at: NOW - 60, time_registered: NOW - 60
+        }], found);
+        assert_eq!(None, found[0].fix());
+    }
+
+    #[test]
+    fn report_rsvp_in_future() {
+        let mut fixture = invitee(1, "Alice");
+        fixture.rsvp = rsvp(Some("+14125550123"), NOW + 1);
+        let found = check(&[fixture], NOW);
+        assert_eq!(vec![Inconsistency::RsvpInFuture { id: InviteId(1), time_registered: NOW + 1 }], found);
+        assert_eq!(None, found[0].fix());
+    }
+
+    #[test]
+    fn repair_untrimmed_name() {
+        let found = check(&[invitee(1, "Alice\n")], NOW);
+        assert_eq!(vec![Inconsistency::UntrimmedName {
+            id: InviteId(1), first_name: String::from("Alice\n"), conflicts: false
+        }], found);
+        assert_eq!(Some(Fix::TrimName(InviteId(1), String::from("Alice"))), found[0].fix());
+    }
+
+    #[test]
+    fn untrimmed_name_taken() {
+        let found = check(&[invitee(1, " Alice"), invitee(2, "Alice")], NOW);
+        assert_eq!(1, found.len());
+        assert_eq!(None, found[0].fix());
+        assert!(found[0].to_string().contains("already has the trimmed name"), "{}", found[0]);
+    }
+
+    #[test]
+    fn untrimmed_name_taken_in_other_event() {
+        let mut other_event = invitee(2, "Alice");
+        other_event.event_id = DEFAULT_EVENT_ID + 1;
+        let found = check(&[invitee(1, " Alice"), other_event], NOW);
+        assert_eq!(Some(Fix::TrimName(InviteId(1), String::from("Alice"))), found[0].fix());
+    }
+}
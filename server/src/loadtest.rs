@@ -0,0 +1,213 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use async_std::net::TcpStream;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+use eyre::Result;
+use hyper::{Body, Request, Uri};
+use hyper::client::conn::{self, SendRequest};
+use thebestofcmu_common::{ClientRSVP, RsvpDetails};
+use crate::app::compat::HyperStream;
+
+/// Settings for a load test, parsed from command line arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadTest {
+    pub concurrency: usize,
+    pub count: usize,
+    /// The enter-rsvp endpoint, such as http://localhost:8080/enter-rsvp
+    pub url: Uri
+}
+
+/// The outcome of a single request: its status code, or "error" if it failed
+type Sample = (Duration, String);
+
+impl LoadTest {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let value_of = |name: &str| args.windows(2)
+            .find(|pair| pair[0] == name)
+            .map(|pair| pair[1].as_str());
+        let concurrency = value_of("--concurrency").unwrap_or("1").parse()?;
+        let count = value_of("--count").unwrap_or("100").parse()?;
+        let url: Uri = value_of("--url")
+            .ok_or_else(|| eyre::eyre!("Pass the --url of the enter-rsvp endpoint"))?
+            .parse()?;
+        if url.scheme_str() != Some("http") || url.host().is_none() {
+            return Err(eyre::eyre!("Only http:// URLs can be load tested: {}", url));
+        }
+        if concurrency == 0 {
+            return Err(eyre::eyre!("Concurrency must be at least 1"));
+        }
+        Ok(Self { concurrency, count, url })
+    }
+
+    /// Fires the RSVPs and prints throughput, latency percentiles and status codes
+    pub async fn run(self) -> Result<()> {
+        let run_id = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let next = Arc::new(AtomicUsize::new(0));
+        let samples = Arc::new(Mutex::new(Vec::with_capacity(self.count)));
+        let this = Arc::new(self);
+
+        let start = Instant::now();
+        let workers: Vec<_> = (0..this.concurrency)
+            .map(|_| task::spawn(this.clone().worker(run_id, next.clone(), samples.clone())))
+            .collect();
+        for worker in workers {
+            worker.await;
+        }
+        let elapsed = start.elapsed();
+
+        let samples = samples.lock().await;
+        println!("{}", summarize(&samples, elapsed));
+        Ok(())
+    }
+
+    async fn worker(self: Arc<Self>, run_id: u64, next: Arc<AtomicUsize>, samples: Arc<Mutex<Vec<Sample>>>) {
+        let mut sender = None;
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            if index >= self.count {
+                return;
+            }
+            let start = Instant::now();
+            let outcome = match self.send_rsvp(&mut sender, format!("loadtest-{}-{}", run_id, index)).await {
+                Ok(status) => status.as_u16().to_string(),
+                Err(e) => {
+                    log::debug!("Load test request failed: {}", e);
+                    // Reconnect for the next request
+                    sender = None;
+                    String::from("error")
+                }
+            };
+            samples.lock().await.push((start.elapsed(), outcome));
+        }
+    }
+
+    async fn send_rsvp(&self,
+                       sender: &mut Option<SendRequest<Body>>,
+                       first_name: String) -> Result<hyper::StatusCode> {
+        let sender = match sender {
+            Some(sender) => sender,
+            None => sender.insert(self.connect().await?)
+        };
+        let rsvp = ClientRSVP {
+            first_name,
+            invite_id: None,
+            details: RsvpDetails {
+                phone_number: None,
+                email_address: Some(String::from("loadtest@example.com")),
+                attending: true,
+                guest_count: 1
+            }
+        };
+        let request = Request::post(self.url.clone())
+            .header(hyper::header::HOST, self.url.authority().map(|authority| authority.as_str()).unwrap_or(""))
+            .body(rsvp.encode()?)?;
+        async_std::future::poll_fn(|cx| sender.poll_ready(cx)).await?;
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        hyper::body::to_bytes(response.into_body()).await?;
+        Ok(status)
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Body>> {
+        let host = self.url.host().unwrap_or("localhost");
+        let port = self.url.port_u16().unwrap_or(80);
+        let stream = TcpStream::connect((host, port)).await?;
+        let (sender, connection) = conn::handshake(HyperStream::unlimited(stream)).await?;
+        task::spawn(async move {
+            if let Err(e) = connection.await {
+                log::debug!("Load test connection closed: {}", e);
+            }
+        });
+        Ok(sender)
+    }
+}
+
+// Nearest-rank percentile of latencies sorted in ascending order
+fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn summarize(samples: &[Sample], elapsed: Duration) -> String {
+    let mut latencies: Vec<Duration> = samples.iter().map(|(latency, _)| *latency).collect();
+    latencies.sort();
+    let mut statuses = BTreeMap::new();
+    for (_, status) in samples {
+        *statuses.entry(status.as_str()).or_insert(0) += 1;
+    }
+
+    let mut summary = format!("{} requests in {:.2}s ({:.1} requests/s)\n",
+                              samples.len(), elapsed.as_secs_f64(),
+                              samples.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON));
+    for rank in [50.0, 90.0, 99.0] {
+        if let Some(latency) = percentile(&latencies, rank) {
+            summary.push_str(&format!("p{}: {:.1}ms\n", rank, latency.as_secs_f64() * 1000.0));
+        }
+    }
+    for (status, count) in statuses {
+        summary.push_str(&format!("{}: {}\n", status, count));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(latencies: &[u64]) -> Vec<Duration> {
+        latencies.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let latencies = millis(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(Some(Duration::from_millis(5)), percentile(&latencies, 50.0));
+        assert_eq!(Some(Duration::from_millis(9)), percentile(&latencies, 90.0));
+        assert_eq!(Some(Duration::from_millis(10)), percentile(&latencies, 99.0));
+        assert_eq!(Some(Duration::from_millis(1)), percentile(&latencies, 0.0));
+        assert_eq!(Some(Duration::from_millis(10)), percentile(&latencies, 100.0));
+
+        assert_eq!(Some(Duration::from_millis(7)), percentile(&millis(&[7]), 99.0));
+        assert_eq!(None, percentile(&[], 50.0));
+    }
+
+    #[test]
+    fn parse_args() -> Result<()> {
+        let args: Vec<String> = ["loadtest", "--concurrency", "8", "--count", "500", "--url", "http://localhost:8080/enter-rsvp"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let load_test = LoadTest::from_args(&args)?;
+        assert_eq!(8, load_test.concurrency);
+        assert_eq!(500, load_test.count);
+        assert_eq!("/enter-rsvp", load_test.url.path());
+
+        let args: Vec<String> = ["loadtest", "--url", "https://localhost/enter-rsvp"]
+            .iter().map(|arg| arg.to_string()).collect();
+        assert!(LoadTest::from_args(&args).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::io::Write;
+use eyre::Result;
+use log::{LevelFilter, Log, Metadata, Record};
+use log::kv::{Key, Value};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LogFormat {
+    Plain,
+    Json
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Plain
+    }
+}
+
+pub fn init(format: LogFormat, level: LevelFilter) -> Result<()> {
+    match format {
+        LogFormat::Plain => simple_logging::log_to_stderr(level),
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger { level }))?;
+            log::set_max_level(level);
+        }
+    }
+    Ok(())
+}
+
+/// Emits one JSON object per line for each log record
+pub struct JsonLogger {
+    level: LevelFilter
+}
+
+impl JsonLogger {
+    fn format_record(record: &Record) -> String {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let mut object = serde_json::json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string()
+        });
+        for key in ["request_id", "client_ip", "method", "path", "status", "elapsed_ms"] {
+            if let Some(value) = record.key_values().get(Key::from_str(key)) {
+                object[key] = Self::json_value(&value);
+            }
+        }
+        object.to_string()
+    }
+
+    // Numbers stay numbers, so status codes and latencies can be queried as such
+    fn json_value(value: &Value) -> serde_json::Value {
+        if let Some(number) = value.to_u64() {
+            serde_json::Value::from(number)
+        } else if let Some(number) = value.to_f64() {
+            serde_json::Value::from(number)
+        } else {
+            serde_json::Value::from(value.to_string())
+        }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = Self::format_record(record);
+        let stderr = std::io::stderr();
+        let mut stderr = stderr.lock();
+        // Nothing sensible can be done if stderr is unwritable
+        let _ = writeln!(stderr, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use log::kv::ToValue;
+
+    #[test]
+    fn json_record_is_parseable() -> Result<()> {
+        let line = JsonLogger::format_record(&Record::builder()
+            .level(Level::Warn)
+            .target("thebestofcmu::app")
+            .args(format_args!("Received bad client data: {}", "\"quoted\""))
+            .build());
+        let object: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!("WARN", object["level"]);
+        assert_eq!("thebestofcmu::app", object["target"]);
+        assert_eq!("Received bad client data: \"quoted\"", object["message"]);
+        assert!(object["timestamp"].is_string());
+        assert!(object.get("request_id").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn json_record_includes_request_id() -> Result<()> {
+        let line = JsonLogger::format_record(&Record::builder()
+            .level(Level::Info)
+            .target("thebestofcmu::app")
+            .args(format_args!("GET /"))
+            .key_values(&("request_id", "42"))
+            .build());
+        let object: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!("42", object["request_id"]);
+        assert!(object.get("client_ip").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn json_record_includes_client_ip() -> Result<()> {
+        let line = JsonLogger::format_record(&Record::builder()
+            .level(Level::Debug)
+            .target("thebestofcmu::app")
+            .args(format_args!("Rate limited"))
+            .key_values(&("client_ip", "203.0.113.7"))
+            .build());
+        let object: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!("203.0.113.7", object["client_ip"]);
+        Ok(())
+    }
+
+    #[test]
+    fn json_access_record_keeps_numbers() -> Result<()> {
+        let key_values: &[(&str, &dyn ToValue)] = &[
+            ("method", &"GET"), ("path", &"/"), ("status", &200u16), ("elapsed_ms", &1.5f64)
+        ];
+        let line = JsonLogger::format_record(&Record::builder()
+            .level(Level::Info)
+            .target("thebestofcmu::access")
+            .args(format_args!("GET / 200 1.5ms"))
+            .key_values(&key_values)
+            .build());
+        let object: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!("GET", object["method"]);
+        assert_eq!("/", object["path"]);
+        assert_eq!(200, object["status"]);
+        assert_eq!(1.5, object["elapsed_ms"]);
+        Ok(())
+    }
+}
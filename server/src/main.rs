@@ -23,24 +23,53 @@
 extern crate core;
 
 use std::net::SocketAddr;
-use async_ctrlc::CtrlC;
-use async_std::{fs, io, sync, task};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use async_std::{future, io, sync, task};
+use async_std::channel::Receiver;
 use async_std::prelude::FutureExt;
 use eyre::Result;
-use rustls::RootCertStore;
-use rustls::server::{AllowAnyAuthenticatedClient, NoClientAuth};
+use hyper::header::HeaderValue;
+use rustls::{ConfigBuilder, RootCertStore, ServerConfig};
+use rustls::server::{AllowAnyAuthenticatedClient, NoClientAuth, WantsServerCert};
+use rustls_pemfile::Item;
+use thebestofcmu_common::ServerResponse;
 use crate::app::App;
-use crate::cli::Cli;
-use crate::config::ConfigFile;
+use crate::certificates::ReloadableCertResolver;
+use crate::cli::{Cli, OneShot};
+use crate::config::{ConfigDir, ConfigFile, Tls};
 use crate::database::Database;
-use crate::website::Website;
+use crate::deadline::Deadline;
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+use crate::rsvp_queue::{QueuedRsvp, RsvpQueue};
+use crate::shutdown::ShutdownSignals;
+use crate::website::{Asset, Website};
 
+mod certificates;
 mod config;
+mod connection_limit;
+mod csv;
 mod method;
+mod metrics;
 mod app;
 mod website;
 mod cli;
 mod database;
+mod deadline;
+mod event;
+mod forwarded;
+mod fsck;
+mod logging;
+#[cfg(feature = "loadtest")]
+mod loadtest;
+mod report;
+mod rate_limit;
+mod rsvp_queue;
+mod self_signed;
+mod shutdown;
+#[cfg(test)]
+mod captured_log;
 
 fn main() -> core::result::Result<(), eyre::Error> {
     use std::env;
@@ -54,19 +83,81 @@ fn main() -> core::result::Result<(), eyre::Error> {
     task::block_on(async_main())
 }
 
+/// What to do, as told by the first argument. Without one, the server is started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Serve,
+    PrintConfig,
+    LoadTest,
+    GenCert,
+    ResetDb,
+    Fsck,
+    Cli
+}
+
+const USAGE: &str = "Usage: thebestofcmu-server [print-config | loadtest | gen-cert | reset-db | fsck | cli]";
+
+// Takes the arguments after the program name
+fn parse_command(args: &[String]) -> Result<Command> {
+    Ok(match args.first().map(String::as_str) {
+        None => Command::Serve,
+        Some("print-config") => Command::PrintConfig,
+        Some("loadtest") => Command::LoadTest,
+        Some("gen-cert") => Command::GenCert,
+        Some("reset-db") => Command::ResetDb,
+        Some("fsck") => Command::Fsck,
+        Some("cli") | Some("--cli") => Command::Cli,
+        Some(other) => return Err(eyre::eyre!("Unknown command {}\n{}", other, USAGE))
+    })
+}
+
 async fn async_main() -> Result<()> {
-    fs::create_dir_all("config").await?;
+    let config_dir = ConfigDir::from_env();
+    config_dir.create().await?;
+
+    let config = config::Config::load(&config_dir.file("config.ron", "CONFIG_RON")).await?;
 
-    let config = config::Config::load(&ConfigFile::new("config/config.ron", "CONFIG_RON")).await?;
+    logging::init(config.log_format, config.log_level())?;
+    let utc_offset = config.utc_offset()?;
+    let rsvp_deadline = config.rsvp_deadline()?;
+    let metrics_address = config.metrics_address()?;
 
-    simple_logging::log_to_stderr(config.log_level());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = parse_command(&args)?;
+    if command == Command::PrintConfig {
+        let show_secrets = args.iter().any(|arg| arg == "--show-secrets");
+        println!("{}", config.display(show_secrets)?);
+        return Ok(());
+    }
+
+    if command == Command::LoadTest {
+        return run_load_test(&args).await;
+    }
 
-    let tls = config.tls;
+    if command == Command::GenCert {
+        let hosts: Vec<String> = args.windows(2)
+            .filter(|pair| pair[0] == "--host")
+            .map(|pair| pair[1].clone())
+            .collect();
+        let force = args.iter().any(|arg| arg == "--force");
+        let certificate_file = config_dir.file("server-certificate.pem", &config.tls.certificate_env);
+        let key_file = config_dir.file("server-certificate.key", &config.tls.key_env);
+        self_signed::generate(hosts, certificate_file.path(), key_file.path(), force).await?;
+        println!("Wrote {} and {}", certificate_file.path().display(), key_file.path().display());
+        return Ok(());
+    }
+
+    let tls = config.tls.clone();
     let tls = if tls.enable {
 
-        let server_cert_file = ConfigFile::new("config/server-certificate.pem", "SERVER_CERTIFICATE");
-        let server_key_file = ConfigFile::new("config/server-certificate.key", "SERVER_KEY");
-        let client_cert_file = ConfigFile::new("config/client-certificate.pem", "CLIENT_CERTIFICATE");
+        let server_cert_file = config_dir.file("server-certificate.pem", &tls.certificate_env);
+        let server_key_file = config_dir.file("server-certificate.key", &tls.key_env);
+        let client_cert_file = config_dir.file("client-certificate.pem", &tls.client_certificate_env);
+        let mut tls_files = vec![&server_cert_file, &server_key_file];
+        if tls.client_auth {
+            tls_files.push(&client_cert_file);
+        }
+        ConfigFile::require_present("TLS files", &tls_files).await?;
 
         let server_certs = FutureExt::try_join(
             load_certificates(&server_cert_file), load_private_key(&server_key_file)
@@ -87,50 +178,295 @@ async fn async_main() -> Result<()> {
             .try_join(client_auth)
             .await?;
 
-        let mut cfg = rustls::ServerConfig::builder()
+        let resolver = sync::Arc::new(ReloadableCertResolver::new(
+            certificates::certified_key(public_key, &private_key)?
+        ));
+        let reload_interval = match tls.reload_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs))
+        };
+        task::spawn(reload_certificates(
+            resolver.clone(), server_cert_file, server_key_file, reload_interval, certificates::hangups()?
+        ));
+
+        let builder = ServerConfig::builder()
             .with_safe_defaults()
-            .with_client_cert_verifier(client_auth)
-            .with_single_cert(public_key, private_key)?;
-        // Configure ALPN to accept HTTP/2, HTTP/1.1 in that order.
-        cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-        Some(sync::Arc::new(cfg))
+            .with_client_cert_verifier(client_auth);
+        Some(sync::Arc::new(server_config(builder, resolver, &tls)?))
     } else {
         None
     };
 
-    let database = Database {
-        pool: sqlx::postgres::PgPool::connect_lazy(&config.postgres_url)?
-    };
+    let database = Database::connect_lazy(
+        config.database_url(), config.postgres_replica_url.as_deref(), config.unique_names, config.pool_settings())?
+        .with_retries(config.database_retries);
 
-    if let Some(first_arg) = std::env::args().next() {
-        if first_arg == "cli" {
-            let cli = Cli {
-                stdin: io::stdin(),
-                stdout: io::stdout(),
-                database
-            };
-            return cli.start().await;
+    if command == Command::ResetDb {
+        if !args.iter().any(|arg| arg == "--yes") {
+            return Err(eyre::eyre!("Resetting the database deletes all data. Pass --yes to confirm"));
         }
+        database.reset(std::env::var_os("ALLOW_DESTRUCTIVE").is_some()).await?;
+        log::info!("Reset the database");
+        return Ok(());
+    }
+
+    if command == Command::Fsck {
+        return run_fsck(&database, args.iter().any(|arg| arg == "--fix")).await;
+    }
+
+    if command == Command::Cli {
+        let one_shot = OneShot::parse(&args[1..])?;
+        let event_slug = args.windows(2)
+            .find(|pair| pair[0] == "--event")
+            .map(|pair| pair[1].as_str());
+        let event_id = match event_slug {
+            Some(slug) => match database.select_event(slug).await? {
+                Some(event) => event.id,
+                None => return Err(eyre::eyre!("No event with slug {}. Add it with add-event", slug))
+            },
+            None => event::DEFAULT_EVENT_ID
+        };
+        let mut cli = Cli {
+            stdin: io::BufReader::new(io::stdin()),
+            stdout: io::stdout(),
+            database,
+            utc_offset,
+            event_id
+        };
+        return match one_shot {
+            Some(command) => cli.run_once(command).await,
+            None => cli.start().await
+        };
+    }
+    let rsvp_queue = config.rsvp_queue_path.clone()
+        .map(|path| sync::Arc::new(RsvpQueue::new(path)));
+    if let Some(rsvp_queue) = &rsvp_queue {
+        task::spawn(drain_rsvp_queue(rsvp_queue.clone(), database.clone()));
     }
+    let footer_html = config.footer_html.clone()
+        .unwrap_or_else(|| String::from(website::DEFAULT_FOOTER));
+    let main_page = website::main_page(config.minify_html, &footer_html, &config.event_details)?;
+    let content_security_policy = match &config.content_security_policy {
+        Some(policy) => policy.clone(),
+        None => website::content_security_policy(&main_page)
+    };
     let app = App {
         database,
         website: Website {
-            favicon: include_bytes!("icons8-fantasy-32.png"),
-            kayaking_image: include_bytes!("kayaking-background.webp")
+            favicon: Asset::new(include_bytes!("icons8-fantasy-32.png")),
+            kayaking_image: Asset::new(include_bytes!("kayaking-background.webp")),
+            main_page_gzip: website::gzip(&main_page)?,
+            main_page,
+            minify_html: config.minify_html,
+            event_details: config.event_details.clone(),
+            footer_html
+        },
+        request_timeout: Duration::from_secs(config.request_timeout_secs),
+        max_connections_per_ip: config.max_connections_per_ip,
+        max_header_count: config.max_header_count,
+        max_header_bytes: config.max_header_bytes,
+        max_body_bytes: config.max_body_bytes,
+        allowed_hosts: config.allowed_hosts.iter()
+            .map(|host| host.to_ascii_lowercase())
+            .collect(),
+        rsvp_queue,
+        utc_offset,
+        rsvp_deadline,
+        require_https_for_rsvp: config.require_https_for_rsvp,
+        trusted_proxies: config.trusted_proxies()?,
+        pretty_json: config.pretty_json,
+        max_party_size: config.max_party_size,
+        rsvp_rate_limiter: RateLimiter::new(config.max_rsvps_per_window,
+                                            Duration::from_secs(config.rsvp_window_secs)),
+        cors_allowed_origins: config.cors_allowed_origins.iter()
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect(),
+        content_security_policy: HeaderValue::from_str(&content_security_policy)
+            .map_err(|e| eyre::eyre!("Invalid Content-Security-Policy {}: {}", content_security_policy, e))?,
+        metrics: sync::Arc::new(Metrics::default()),
+        serves_metrics: metrics_address.is_none(),
+        serves_https: false,
+        schema_ready: sync::Arc::new(AtomicBool::new(false)),
+        shutting_down: sync::Arc::new(AtomicBool::new(false)),
+        shutdown_timeout: Duration::from_secs(config.shutdown_timeout_secs)
+    };
+    // Listen straight away so /readyz can report progress while the schema is updated
+    let migration = {
+        let database = app.database.clone();
+        let schema_ready = app.schema_ready.clone();
+        async move {
+            database.migrate().await?;
+            schema_ready.store(true, Ordering::Release);
+            log::info!("Database schema is up to date");
+            Ok::<_, eyre::Report>(())
         }
     };
-    app.database.create_schema().await?;
+    let metrics_server = serve_metrics(app.metrics.clone(), metrics_address, config.max_connections_per_ip);
+    let signals = ShutdownSignals::register()?;
+    if let Some(path) = config.host.strip_prefix("unix:") {
+        if tls.is_some() {
+            return Err(eyre::eyre!("TLS is not supported on a Unix socket. Terminate TLS at the proxy instead"));
+        }
+        return start_unix_server(app, path, signals, migration).race(metrics_server).await;
+    }
     let socket =  SocketAddr::new(config.host.parse()?, config.port);
-    app.start_server(socket, tls, shutdown_signal()).await
+    let served = async {
+        app.start_server(socket, tls, shutdown::shutdown_on(signals.recv()))
+            .try_join(migration)
+            .await?;
+        Ok::<_, eyre::Report>(())
+    };
+    // The metrics server has no shutdown of its own. It stops when the site does.
+    served.race(metrics_server).await
+}
+
+async fn serve_metrics(metrics: sync::Arc<Metrics>,
+                       address: Option<SocketAddr>,
+                       max_connections_per_ip: usize) -> Result<()> {
+    match address {
+        Some(address) => metrics::serve(metrics, address, max_connections_per_ip).await,
+        // Served alongside the site instead
+        None => future::pending().await
+    }
+}
+
+#[cfg(unix)]
+async fn start_unix_server<M>(app: App, path: &str, signals: ShutdownSignals, migration: M) -> Result<()>
+    where M: std::future::Future<Output=Result<()>> {
+
+    app.start_unix_server(path.as_ref(), shutdown::shutdown_on(signals.recv()))
+        .try_join(migration)
+        .await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn start_unix_server<M>(_app: App, _path: &str, _signals: ShutdownSignals, _migration: M) -> Result<()> {
+    Err(eyre::eyre!("Unix sockets are not supported on this platform"))
+}
+
+async fn run_fsck(database: &Database, fix: bool) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let inconsistencies = fsck::check(&database.select_stored_invitees().await?, now);
+    if inconsistencies.is_empty() {
+        println!("No inconsistencies found");
+        return Ok(());
+    }
+    let mut fixes = Vec::new();
+    for inconsistency in &inconsistencies {
+        match inconsistency.fix() {
+            Some(repair) => {
+                println!("{}. Fix: {}", inconsistency, repair);
+                fixes.push(repair);
+            },
+            None => println!("{}. No safe fix; please review it by hand", inconsistency)
+        }
+    }
+    println!("Found {} inconsistencies, of which {} can be fixed", inconsistencies.len(), fixes.len());
+    if !fix || fixes.is_empty() {
+        return Ok(());
+    }
+    println!("Type 'yes' to apply the {} fixes", fixes.len());
+    let mut confirmation = String::new();
+    io::stdin().read_line(&mut confirmation).await?;
+    if confirmation.trim() != "yes" {
+        println!("Not confirmed, so nothing was changed");
+        return Ok(());
+    }
+    database.apply_fixes(&fixes).await?;
+    log::info!("Applied {} fixes", fixes.len());
+    Ok(())
+}
+
+#[cfg(feature = "loadtest")]
+async fn run_load_test(args: &[String]) -> Result<()> {
+    loadtest::LoadTest::from_args(args)?.run().await
 }
 
-async fn shutdown_signal() {
-    CtrlC::new().expect("Cannot create CTRL+C handler").await;
-    log::info!("Shutting down....");
+#[cfg(not(feature = "loadtest"))]
+async fn run_load_test(_args: &[String]) -> Result<()> {
+    Err(eyre::eyre!("The loadtest command requires building with --features loadtest"))
 }
 
-async fn load_certificates(path: &ConfigFile<'_>) -> Result<Vec<rustls::Certificate>> {
-    let certificate = path.read_content().await?;
+async fn drain_rsvp_queue(rsvp_queue: sync::Arc<RsvpQueue>, database: Database) {
+    loop {
+        task::sleep(Duration::from_secs(10)).await;
+        let drained = rsvp_queue.drain(|queued| record_queued_rsvp(&database, queued)).await;
+        match drained {
+            Ok(0) => {},
+            Ok(drained) => log::info!("Recorded {} queued RSVPs", drained),
+            Err(e) => log::error!("Unable to drain queued RSVPs: {}", e)
+        }
+    }
+}
+
+// The client was told its RSVP was queued, so one which is refused now must not vanish quietly
+async fn record_queued_rsvp(database: &Database, queued: QueuedRsvp) -> Result<()> {
+    let deadline = Deadline::after(Duration::from_secs(30));
+    let first_name = queued.rsvp.first_name.clone();
+    let response = database.insert_rsvp_at(
+        queued.rsvp, queued.event_id, queued.submitted_at, deadline
+    ).await?;
+    if response == ServerResponse::Success {
+        log::debug!("Recorded queued RSVP for {}", first_name);
+    } else {
+        log::warn!(first_name = first_name.as_str(); "Queued RSVP for {} was not recorded: {:?}", first_name, response);
+    }
+    Ok(())
+}
+
+fn server_config(builder: ConfigBuilder<ServerConfig, WantsServerCert>,
+                 resolver: sync::Arc<ReloadableCertResolver>,
+                 tls: &Tls) -> Result<ServerConfig> {
+    let mut cfg = builder.with_cert_resolver(resolver);
+    cfg.alpn_protocols = tls.alpn_protocols()?;
+    Ok(cfg)
+}
+
+// Reloads the server certificate and key every interval, and whenever a hangup arrives.
+// If they cannot be loaded, the certificate in use is kept.
+async fn reload_certificates(resolver: sync::Arc<ReloadableCertResolver>,
+                             certificate_file: ConfigFile,
+                             key_file: ConfigFile,
+                             interval: Option<Duration>,
+                             hangups: Receiver<()>) {
+    loop {
+        let periodic = async {
+            match interval {
+                Some(interval) => task::sleep(interval).await,
+                None => future::pending().await
+            }
+        };
+        let hangup = async {
+            if hangups.recv().await.is_err() {
+                future::pending().await
+            }
+        };
+        periodic.race(hangup).await;
+
+        let loaded = async {
+            let (certificates, private_key) = FutureExt::try_join(
+                load_certificates(&certificate_file), load_private_key(&key_file)
+            ).await?;
+            certificates::certified_key(certificates, &private_key)
+        };
+        match loaded.await {
+            Ok(certified_key) => {
+                resolver.swap(certified_key);
+                log::info!("Reloaded the server certificate");
+            },
+            Err(e) => log::error!("Unable to reload the server certificate, so the current one is kept: {}", e)
+        }
+    }
+}
+
+async fn load_certificates(path: &ConfigFile) -> Result<Vec<rustls::Certificate>> {
+    parse_certificates(&path.read_content().await?)
+}
+
+fn parse_certificates(certificate: &str) -> Result<Vec<rustls::Certificate>> {
     let mut cert_reader = std::io::Cursor::new(certificate);
     Ok(rustls_pemfile::certs(&mut cert_reader)?
         .into_iter()
@@ -138,10 +474,19 @@ async fn load_certificates(path: &ConfigFile<'_>) -> Result<Vec<rustls::Certific
         .collect())
 }
 
-async fn load_private_key(path: &ConfigFile<'_>) -> Result<rustls::PrivateKey> {
-    let private_key = path.read_content().await?;
+async fn load_private_key(path: &ConfigFile) -> Result<rustls::PrivateKey> {
+    parse_private_key(&path.read_content().await?)
+}
+
+// Accepts PKCS#8 keys, traditional RSA keys and SEC1 EC keys, but only one of them
+fn parse_private_key(private_key: &str) -> Result<rustls::PrivateKey> {
     let mut private_key_reader = std::io::Cursor::new(private_key);
-    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut private_key_reader)?.into_iter();
+    let mut keys = rustls_pemfile::read_all(&mut private_key_reader)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(key),
+            _ => None
+        });
 
     return if let Some(private_key) = keys.next() {
         if let Some(_) = keys.next() {
@@ -154,4 +499,114 @@ async fn load_private_key(path: &ConfigFile<'_>) -> Result<rustls::PrivateKey> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().copied().map(String::from).collect()
+    }
+
+    #[async_std::test]
+    async fn refused_queued_rsvp_logged() -> Result<()> {
+        captured_log::install();
+        let database = Database::connect_lazy("sqlite::memory:", None, true, database::PoolSettings::default())?;
+        database.migrate().await?;
+        let rsvp = thebestofcmu_common::ClientRSVP {
+            first_name: String::from("Mallory"),
+            invite_id: None,
+            details: thebestofcmu_common::RsvpDetails {
+                phone_number: None,
+                email_address: Some(String::from("mallory@example.com")),
+                attending: true,
+                guest_count: 1
+            }
+        };
+        let queued = QueuedRsvp { rsvp, event_id: event::DEFAULT_EVENT_ID, submitted_at: 1_662_089_400 };
+        record_queued_rsvp(&database, queued).await?;
+        let records = captured_log::records(|record| record.fields.get("first_name").map(String::as_str) == Some("Mallory"));
+        assert_eq!(vec!["Queued RSVP for Mallory was not recorded: NotInvited"],
+                   records.iter().map(|record| record.message.as_str()).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn command_from_first_argument() -> Result<()> {
+        assert_eq!(Command::Serve, parse_command(&[])?);
+        assert_eq!(Command::Cli, parse_command(&args(&["cli"]))?);
+        assert_eq!(Command::Cli, parse_command(&args(&["--cli", "--event", "fall-trip"]))?);
+        assert_eq!(Command::ResetDb, parse_command(&args(&["reset-db", "--yes"]))?);
+        assert_eq!(Command::PrintConfig, parse_command(&args(&["print-config", "cli"]))?);
+        Ok(())
+    }
+
+    #[test]
+    fn private_key_formats() -> Result<()> {
+        for pem in [
+            include_str!("test-keys/pkcs8.key"),
+            include_str!("test-keys/rsa.key"),
+            include_str!("test-keys/ec.key")
+        ] {
+            let private_key = parse_private_key(pem)?;
+            assert!(rustls::sign::any_supported_type(&private_key).is_ok(), "{}", pem.lines().next().unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn one_private_key_only() {
+        let two_keys = format!("{}{}", include_str!("test-keys/pkcs8.key"), include_str!("test-keys/ec.key"));
+        assert_eq!("Too many keys", parse_private_key(&two_keys).unwrap_err().to_string());
+        let no_keys = "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n";
+        assert_eq!("No private keys found", parse_private_key(no_keys).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn certificate_chain() -> Result<()> {
+        let certificates = parse_certificates(include_str!("test-keys/certificate.pem"))?;
+        assert_eq!(1, certificates.len());
+        let private_key = parse_private_key(include_str!("test-keys/ec.key"))?;
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certificates, private_key)?;
+        Ok(())
+    }
+
+    #[test]
+    fn configured_alpn_protocols() -> Result<()> {
+        let resolver = sync::Arc::new(ReloadableCertResolver::new(certificates::certified_key(
+            parse_certificates(include_str!("test-keys/certificate.pem"))?,
+            &parse_private_key(include_str!("test-keys/ec.key"))?
+        )?));
+        let tls = Tls { alpn: vec![String::from("http/1.1")], ..Default::default() };
+        let builder = ServerConfig::builder().with_safe_defaults().with_no_client_auth();
+        let server_config = server_config(builder, resolver, &tls)?;
+        assert_eq!(vec![b"http/1.1".to_vec()], server_config.alpn_protocols);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn certificate_and_key_from_environment() -> Result<()> {
+        let parent = tempfile::tempdir()?;
+        std::env::set_var("THEBESTOFCMU_TEST_CERTIFICATE_PEM", include_str!("test-keys/certificate.pem"));
+        std::env::set_var("THEBESTOFCMU_TEST_KEY_PEM", include_str!("test-keys/ec.key"));
+        let certificate = ConfigFile::new(parent.path().join("server-certificate.pem"), "THEBESTOFCMU_TEST_CERTIFICATE_PEM");
+        let key = ConfigFile::new(parent.path().join("server-certificate.key"), "THEBESTOFCMU_TEST_KEY_PEM");
+        let loaded = FutureExt::try_join(load_certificates(&certificate), load_private_key(&key)).await;
+        std::env::remove_var("THEBESTOFCMU_TEST_CERTIFICATE_PEM");
+        std::env::remove_var("THEBESTOFCMU_TEST_KEY_PEM");
+
+        let (certificates, private_key) = loaded?;
+        assert_eq!(parse_certificates(include_str!("test-keys/certificate.pem"))?, certificates);
+        assert_eq!(parse_private_key(include_str!("test-keys/ec.key"))?, private_key);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_command() {
+        let error = parse_command(&args(&["serve-forever"])).unwrap_err().to_string();
+        assert!(error.contains("Unknown command serve-forever"), "{}", error);
+        assert!(error.contains(USAGE), "{}", error);
+    }
+}
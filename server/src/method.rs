@@ -19,15 +19,17 @@
 
 use hyper::{Method, Response, Body, http, StatusCode};
 use eyre::Result;
-use crate::method::AllowedMethod::{GET, HEAD, POST};
+use crate::method::AllowedMethod::{GET, HEAD, OPTIONS, POST};
 
-const ALL_ALLOWED: &[AllowedMethod] = &[GET, HEAD, POST];
+const ALL_ALLOWED: &[AllowedMethod] = &[GET, HEAD, POST, OPTIONS];
 
 #[derive(Debug, Copy, Clone)]
 pub enum AllowedMethod {
     GET,
     HEAD,
-    POST
+    POST,
+    /// Sent by browsers as a CORS preflight before cross-origin POSTs
+    OPTIONS
 }
 
 impl From<&AllowedMethod> for Method {
@@ -36,7 +38,8 @@ impl From<&AllowedMethod> for Method {
         match allowed_method {
             GET => Method::GET,
             HEAD => Method::HEAD,
-            POST => Method::POST
+            POST => Method::POST,
+            OPTIONS => Method::OPTIONS
         }
     }
 }
@@ -48,6 +51,9 @@ impl AllowedMethod {
             &Method::GET => GET,
             &Method::HEAD => HEAD,
             &Method::POST => POST,
+            &Method::OPTIONS => OPTIONS,
+            // Includes CONNECT and TRACE. Answering CONNECT with anything but a 2xx
+            // keeps hyper from treating the connection as a tunnel.
             _ => return None
         })
     }
@@ -93,7 +99,7 @@ mod tests {
 
     #[test]
     fn convert_methods() {
-        for method in &[Method::GET, Method::HEAD] {
+        for method in &[Method::GET, Method::HEAD, Method::POST, Method::OPTIONS] {
             let allowed_method = AllowedMethod::find_from(method).unwrap();
             let back: Method = (&allowed_method).into();
             assert_eq!(method, back);
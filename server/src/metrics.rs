@@ -0,0 +1,225 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use async_std::net::TcpListener;
+use eyre::Result;
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use hyper::http::version;
+use hyper::service::{make_service_fn, service_fn};
+use crate::app::compat;
+use crate::connection_limit::ConnectionTracker;
+
+/// The Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const METHOD_LABELS: [&str; 5] = ["GET", "HEAD", "POST", "OPTIONS", "other"];
+
+const STATUS_CLASS_LABELS: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+// Upper bounds in seconds. Requests slower than the request timeout land only in +Inf.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counts requests and RSVPs since the server started, for Prometheus to scrape
+#[derive(Default)]
+pub struct Metrics {
+    requests: [AtomicU64; METHOD_LABELS.len()],
+    responses: [AtomicU64; STATUS_CLASS_LABELS.len()],
+    rsvps_accepted: AtomicU64,
+    rsvps_rejected: AtomicU64,
+    // Each bucket counts the requests answered within its bound, so they are cumulative
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_sum_micros: AtomicU64,
+    latency_count: AtomicU64
+}
+
+impl Metrics {
+    /// Counts an answered request, with how long it took to answer
+    pub fn record_request(&self, method: &Method, status: StatusCode, elapsed: Duration) {
+        let method_index = match *method {
+            Method::GET => 0,
+            Method::HEAD => 1,
+            Method::POST => 2,
+            Method::OPTIONS => 3,
+            _ => 4
+        };
+        self.requests[method_index].fetch_add(1, Ordering::Relaxed);
+        let status_class = usize::from(status.as_u16() / 100).clamp(1, 5) - 1;
+        self.responses[status_class].fetch_add(1, Ordering::Relaxed);
+
+        let elapsed_secs = elapsed.as_secs_f64();
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            if elapsed_secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let elapsed_micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.latency_sum_micros.fetch_add(elapsed_micros, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an RSVP which was recorded, or queued to be
+    pub fn record_rsvp_accepted(&self) {
+        self.rsvps_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an RSVP which was invalid, or refused by the database
+    pub fn record_rsvp_rejected(&self) {
+        self.rsvps_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Writes every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        self.write_to(&mut text).expect("Writing to a String cannot fail");
+        text
+    }
+
+    fn write_to(&self, text: &mut String) -> std::fmt::Result {
+        writeln!(text, "# HELP thebestofcmu_http_requests_total HTTP requests answered, by method")?;
+        writeln!(text, "# TYPE thebestofcmu_http_requests_total counter")?;
+        for (label, count) in METHOD_LABELS.iter().zip(&self.requests) {
+            writeln!(text, "thebestofcmu_http_requests_total{{method=\"{}\"}} {}", label, count.load(Ordering::Relaxed))?;
+        }
+        writeln!(text, "# HELP thebestofcmu_http_responses_total HTTP responses sent, by status class")?;
+        writeln!(text, "# TYPE thebestofcmu_http_responses_total counter")?;
+        for (label, count) in STATUS_CLASS_LABELS.iter().zip(&self.responses) {
+            writeln!(text, "thebestofcmu_http_responses_total{{status=\"{}\"}} {}", label, count.load(Ordering::Relaxed))?;
+        }
+        writeln!(text, "# HELP thebestofcmu_rsvps_total RSVPs received, by whether they were accepted")?;
+        writeln!(text, "# TYPE thebestofcmu_rsvps_total counter")?;
+        writeln!(text, "thebestofcmu_rsvps_total{{outcome=\"accepted\"}} {}", self.rsvps_accepted.load(Ordering::Relaxed))?;
+        writeln!(text, "thebestofcmu_rsvps_total{{outcome=\"rejected\"}} {}", self.rsvps_rejected.load(Ordering::Relaxed))?;
+
+        writeln!(text, "# HELP thebestofcmu_http_request_duration_seconds Time taken to answer HTTP requests")?;
+        writeln!(text, "# TYPE thebestofcmu_http_request_duration_seconds histogram")?;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            writeln!(text, "thebestofcmu_http_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, count.load(Ordering::Relaxed))?;
+        }
+        let latency_count = self.latency_count.load(Ordering::Relaxed);
+        let latency_sum_secs = self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        writeln!(text, "thebestofcmu_http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", latency_count)?;
+        writeln!(text, "thebestofcmu_http_request_duration_seconds_sum {}", latency_sum_secs)?;
+        writeln!(text, "thebestofcmu_http_request_duration_seconds_count {}", latency_count)
+    }
+
+    pub fn response(&self, version: version::Version) -> Result<Response<Body>> {
+        Ok(Response::builder()
+            .version(version)
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::from(self.render()))?)
+    }
+
+    fn respond_to(&self, request: &Request<Body>) -> Result<Response<Body>> {
+        if request.uri().path() == "/metrics" {
+            return self.response(request.version());
+        }
+        Ok(Response::builder()
+            .version(request.version())
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))?)
+    }
+}
+
+/// Serves only /metrics, on an address kept apart from the site so that it need not be public
+pub async fn serve(metrics: Arc<Metrics>, socket: SocketAddr, max_connections_per_ip: usize) -> Result<()> {
+    let listener = TcpListener::bind(&socket).await?;
+    let listener = compat::HyperListener::new(&listener, ConnectionTracker::new(max_connections_per_ip, Vec::new()));
+    log::info!("Serving metrics on {}", socket);
+
+    Server::builder(listener)
+        .executor(compat::HyperExecutor)
+        .serve(make_service_fn(move |_connection| {
+            let metrics = metrics.clone();
+            async move {
+                Ok::<_, eyre::Report>(service_fn(move |request: Request<Body>| {
+                    let response = metrics.respond_to(&request);
+                    async move { response }
+                }))
+            }
+        }))
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_method_and_status_class() {
+        let metrics = Metrics::default();
+        metrics.record_request(&Method::GET, StatusCode::OK, Duration::from_millis(1));
+        metrics.record_request(&Method::GET, StatusCode::NOT_FOUND, Duration::from_millis(1));
+        metrics.record_request(&Method::PUT, StatusCode::METHOD_NOT_ALLOWED, Duration::from_millis(1));
+        metrics.record_request(&Method::POST, StatusCode::INTERNAL_SERVER_ERROR, Duration::from_millis(1));
+
+        let text = metrics.render();
+        assert!(text.contains("thebestofcmu_http_requests_total{method=\"GET\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_requests_total{method=\"POST\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_requests_total{method=\"other\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_responses_total{status=\"2xx\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_responses_total{status=\"4xx\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_responses_total{status=\"5xx\"} 1\n"), "{}", text);
+    }
+
+    #[test]
+    fn latency_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        metrics.record_request(&Method::GET, StatusCode::OK, Duration::from_millis(3));
+        metrics.record_request(&Method::GET, StatusCode::OK, Duration::from_millis(200));
+        metrics.record_request(&Method::GET, StatusCode::OK, Duration::from_secs(60));
+
+        let text = metrics.render();
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_bucket{le=\"0.005\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_bucket{le=\"0.25\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_bucket{le=\"10\"} 2\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_sum 60.203\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_http_request_duration_seconds_count 3\n"), "{}", text);
+    }
+
+    #[test]
+    fn counts_rsvps() {
+        let metrics = Metrics::default();
+        metrics.record_rsvp_accepted();
+        metrics.record_rsvp_rejected();
+        metrics.record_rsvp_rejected();
+
+        let text = metrics.render();
+        assert!(text.contains("thebestofcmu_rsvps_total{outcome=\"accepted\"} 1\n"), "{}", text);
+        assert!(text.contains("thebestofcmu_rsvps_total{outcome=\"rejected\"} 2\n"), "{}", text);
+    }
+
+    #[test]
+    fn every_metric_is_typed() {
+        let text = Metrics::default().render();
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = name.trim_end_matches("_bucket").trim_end_matches("_sum").trim_end_matches("_count");
+            assert!(text.contains(&format!("# TYPE {} ", family)), "Untyped metric: {}", line);
+        }
+    }
+}
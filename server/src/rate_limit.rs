@@ -0,0 +1,117 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Above this many addresses, those whose windows have ended are forgotten
+const PRUNE_ABOVE: usize = 1024;
+
+/// Counts the requests of each client IP address in fixed windows of time
+#[derive(Clone)]
+pub struct RateLimiter {
+    windows: Arc<Mutex<HashMap<IpAddr, Window>>>,
+    max_requests: u32,
+    window: Duration
+}
+
+struct Window {
+    started: Instant,
+    requests: u32
+}
+
+impl RateLimiter {
+    /// Allows each IP address the given number of requests per window. Zero requests
+    /// disables the limit.
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            windows: Arc::new(Mutex::new(HashMap::new())),
+            max_requests,
+            window
+        }
+    }
+
+    /// Counts a request, unless the IP address has used up its window. If it has,
+    /// yields how long until the next window begins.
+    pub fn try_request(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.max_requests == 0 {
+            return Ok(());
+        }
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > PRUNE_ABOVE {
+            windows.retain(|_, window| now < window.started + self.window);
+        }
+        let window = windows.entry(ip).or_insert(Window { started: now, requests: 0 });
+        if now >= window.started + self.window {
+            *window = Window { started: now, requests: 0 };
+        }
+        if window.requests >= self.max_requests {
+            return Err(window.started + self.window - now);
+        }
+        window.requests += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn limit_requests_per_window() {
+        let limiter = RateLimiter::new(3, WINDOW);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(Ok(()), limiter.try_request(ip, start));
+        }
+        assert_eq!(Err(Duration::from_secs(50)), limiter.try_request(ip, start + Duration::from_secs(10)));
+        assert_eq!(Ok(()), limiter.try_request(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)), start));
+
+        // The next window starts afresh
+        assert_eq!(Ok(()), limiter.try_request(ip, start + WINDOW));
+    }
+
+    #[test]
+    fn zero_disables_limit() {
+        let limiter = RateLimiter::new(0, WINDOW);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        for _ in 0..100 {
+            assert_eq!(Ok(()), limiter.try_request(ip, Instant::now()));
+        }
+    }
+
+    #[test]
+    fn forget_ended_windows() {
+        let limiter = RateLimiter::new(1, WINDOW);
+        let start = Instant::now();
+        for host in 0..=PRUNE_ABOVE as u32 {
+            limiter.try_request(IpAddr::V4(Ipv4Addr::from(host)), start).unwrap();
+        }
+        let later = start + WINDOW;
+        limiter.try_request(IpAddr::V4(Ipv4Addr::LOCALHOST), later).unwrap();
+        assert_eq!(1, limiter.windows.lock().unwrap().len());
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use time::{Date, OffsetDateTime};
+use thebestofcmu_common::Invitee;
+use crate::database::SourceStats;
+use crate::website::escape_html;
+
+/// Renders a self-contained, printable HTML summary of invites and RSVPs
+pub fn render_report(invitees: &[Invitee], sources: &[SourceStats]) -> String {
+    let rsvped = invitees.iter().filter(|invitee| invitee.rsvp.is_some()).count();
+
+    let mut html = String::from(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>RSVP report</title>
+</head>
+<body style="font-family: sans-serif; margin: 2em;">
+<h1 style="color: #5e9ca0;">RSVP report</h1>
+"#);
+    // Writing to a String cannot fail
+    let _ = write!(html, r#"<table style="border-collapse: collapse;">
+<tr><th style="text-align: left; padding: 4px;">Invited</th><td style="padding: 4px;">{}</td></tr>
+<tr><th style="text-align: left; padding: 4px;">RSVP'd</th><td style="padding: 4px;">{}</td></tr>
+<tr><th style="text-align: left; padding: 4px;">Not yet responded</th><td style="padding: 4px;">{}</td></tr>
+</table>
+"#, invitees.len(), rsvped, invitees.len() - rsvped);
+
+    html.push_str(r#"<h2>By source</h2>
+<table style="border-collapse: collapse;">
+<tr><th style="padding: 4px;">Source</th><th style="padding: 4px;">Invited</th><th style="padding: 4px;">RSVP'd</th><th style="padding: 4px;">RSVP rate</th></tr>
+"#);
+    for stats in sources {
+        let _ = writeln!(html,
+            r#"<tr><td style="padding: 4px;">{}</td><td style="padding: 4px;">{}</td><td style="padding: 4px;">{}</td><td style="padding: 4px;">{:.0}%</td></tr>"#,
+            escape_html(stats.source.as_deref().unwrap_or("-")), stats.invited, stats.rsvped, stats.rsvp_rate() * 100.0);
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>RSVPs over time</h2>\n");
+    let by_day = rsvps_by_day(invitees);
+    let busiest_day = by_day.values().copied().max().unwrap_or(0);
+    for (day, count) in by_day {
+        let width = count * 100 / busiest_day;
+        let _ = writeln!(html,
+            r#"<div style="margin: 2px 0;"><span style="display: inline-block; width: 7em;">{}</span><span style="display: inline-block; background: #5e9ca0; color: white; padding: 0 4px; width: {}%;">{}</span></div>"#,
+            day, width, count);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn rsvps_by_day(invitees: &[Invitee]) -> BTreeMap<Date, usize> {
+    let mut by_day = BTreeMap::new();
+    for invitee in invitees {
+        if let Some((_, at_time)) = &invitee.rsvp {
+            let at_time: OffsetDateTime = (*at_time).into();
+            *by_day.entry(at_time.date()).or_insert(0) += 1;
+        }
+    }
+    by_day
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use thebestofcmu_common::{InviteId, RsvpDetails};
+
+    fn invitee(id: i32, rsvp_at_secs: Option<u64>) -> Invitee {
+        Invitee {
+            id: InviteId(id),
+            first_name: format!("Invitee {}", id),
+            source: None,
+            admin_note: None,
+            created_at: None,
+            rsvp: rsvp_at_secs.map(|secs| (
+                RsvpDetails { phone_number: None, email_address: None, attending: true, guest_count: 1 },
+                SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+            ))
+        }
+    }
+
+    #[test]
+    fn report_contains_totals() {
+        let invitees = [
+            invitee(1, Some(1_661_990_400)),
+            invitee(2, Some(1_661_990_400 + 3600)),
+            invitee(3, Some(1_662_076_800)),
+            invitee(4, None)
+        ];
+        let sources = [
+            SourceStats { source: Some(String::from("<text>")), invited: 4, rsvped: 3 }
+        ];
+        let html = render_report(&invitees, &sources);
+        assert!(html.contains(r#"Invited</th><td style="padding: 4px;">4</td>"#));
+        assert!(html.contains(r#"RSVP'd</th><td style="padding: 4px;">3</td>"#));
+        assert!(html.contains(r#"Not yet responded</th><td style="padding: 4px;">1</td>"#));
+        assert!(html.contains("&lt;text&gt;"));
+        assert!(html.contains("75%"));
+        assert!(html.contains("2022-09-01"));
+        assert!(html.contains("2022-09-02"));
+    }
+}
@@ -0,0 +1,194 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::future::Future;
+use async_std::fs::{self, OpenOptions};
+use async_std::io::{self, WriteExt};
+use async_std::path::PathBuf;
+use async_std::sync::Mutex;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use thebestofcmu_common::ClientRSVP;
+use crate::event::DEFAULT_EVENT_ID;
+
+/// An RSVP which could not be stored because the database was unreachable
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueuedRsvp {
+    pub rsvp: ClientRSVP,
+    /// RSVPs queued before events existed were for the default event
+    #[serde(default = "default_event_id")]
+    pub event_id: i32,
+    /// Seconds since the Unix epoch at which the RSVP was submitted
+    pub submitted_at: u64
+}
+
+fn default_event_id() -> i32 {
+    DEFAULT_EVENT_ID
+}
+
+/// Append-only file of RSVPs awaiting insertion, one JSON object per line
+pub struct RsvpQueue {
+    path: PathBuf,
+    // Serializes appending with draining
+    lock: Mutex<()>
+}
+
+impl RsvpQueue {
+    pub fn new<P>(path: P) -> Self
+        where P: Into<PathBuf> {
+
+        Self { path: path.into(), lock: Mutex::new(()) }
+    }
+
+    pub async fn enqueue(&self, queued: &QueuedRsvp) -> Result<()> {
+        let mut line = serde_json::to_string(queued)?;
+        line.push('\n');
+
+        let _lock = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    /// Hands each queued RSVP, oldest first, to the insert function. Stops at the first
+    /// failure, keeping that RSVP and all later ones queued. Returns how many were drained.
+    pub async fn drain<F, Fut>(&self, mut insert: F) -> Result<usize>
+        where F: FnMut(QueuedRsvp) -> Fut,
+              Fut: Future<Output=Result<()>> {
+
+        let _lock = self.lock.lock().await;
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into())
+        };
+        let lines: Vec<&str> = content.lines().filter(|line| !line.is_empty()).collect();
+        let mut drained = 0;
+        let mut remaining: &[&str] = &[];
+        for (index, line) in lines.iter().enumerate() {
+            let queued: QueuedRsvp = match serde_json::from_str(line) {
+                Ok(queued) => queued,
+                Err(e) => {
+                    log::error!("Discarding unreadable queued RSVP {}: {}", line, e);
+                    continue;
+                }
+            };
+            if let Err(e) = insert(queued).await {
+                log::warn!("Unable to drain queued RSVPs: {}", e);
+                remaining = &lines[index..];
+                break;
+            }
+            drained += 1;
+        }
+        let mut rest = remaining.join("\n");
+        if !rest.is_empty() {
+            rest.push('\n');
+        }
+        // Replace the file atomically so a crash cannot lose queued RSVPs
+        let temporary_path = self.path.with_extension("tmp");
+        fs::write(&temporary_path, rest).await?;
+        fs::rename(&temporary_path, &self.path).await?;
+        Ok(drained)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thebestofcmu_common::RsvpDetails;
+
+    fn queued(first_name: &str, submitted_at: u64) -> QueuedRsvp {
+        QueuedRsvp {
+            rsvp: ClientRSVP {
+                first_name: String::from(first_name),
+                invite_id: None,
+                details: RsvpDetails { phone_number: Some(String::from("+14125550123")), email_address: None, attending: true, guest_count: 1 }
+            },
+            event_id: DEFAULT_EVENT_ID,
+            submitted_at
+        }
+    }
+
+    #[async_std::test]
+    async fn enqueue_then_drain() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let queue = RsvpQueue::new(directory.path().join("rsvp-queue.jsonl"));
+        queue.enqueue(&queued("Alice", 1)).await?;
+        queue.enqueue(&queued("Bob", 2)).await?;
+
+        let mut inserted = Vec::new();
+        let drained = queue.drain(|queued| {
+            inserted.push(queued);
+            async { Ok(()) }
+        }).await?;
+        assert_eq!(2, drained);
+        assert_eq!(vec![queued("Alice", 1), queued("Bob", 2)], inserted);
+
+        // Nothing is replayed twice
+        let drained = queue.drain(|_| async { Err(eyre::eyre!("Should be empty")) }).await?;
+        assert_eq!(0, drained);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn keep_queued_while_database_down() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let queue = RsvpQueue::new(directory.path().join("rsvp-queue.jsonl"));
+        queue.enqueue(&queued("Alice", 1)).await?;
+        queue.enqueue(&queued("Bob", 2)).await?;
+        queue.enqueue(&queued("Carol", 3)).await?;
+
+        // The database goes down after the first insert
+        let mut attempts = 0;
+        let drained = queue.drain(|_| {
+            attempts += 1;
+            let result = if attempts == 1 { Ok(()) } else { Err(eyre::eyre!("Connection refused")) };
+            async move { result }
+        }).await?;
+        assert_eq!(1, drained);
+
+        let mut inserted = Vec::new();
+        queue.drain(|queued| {
+            inserted.push(queued);
+            async { Ok(()) }
+        }).await?;
+        assert_eq!(vec![queued("Bob", 2), queued("Carol", 3)], inserted);
+        Ok(())
+    }
+
+    #[test]
+    fn queued_before_events() -> Result<()> {
+        let line = r#"{"rsvp":{"first_name":"Alice","details":{"phone_number":4125550123,"email_address":null}},"submitted_at":1}"#;
+        assert_eq!(queued("Alice", 1), serde_json::from_str(line)?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn drain_missing_queue() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let queue = RsvpQueue::new(directory.path().join("rsvp-queue.jsonl"));
+        assert_eq!(0, queue.drain(|_| async { Ok(()) }).await?);
+        Ok(())
+    }
+}
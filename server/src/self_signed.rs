@@ -0,0 +1,81 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+
+use std::path::Path;
+use async_std::fs;
+use eyre::Result;
+
+/// Writes a self-signed certificate and its PKCS#8 private key for the given host names.
+/// Existing files are only replaced if forced to.
+pub async fn generate(hosts: Vec<String>, certificate_path: &Path, key_path: &Path, force: bool) -> Result<()> {
+    if hosts.is_empty() {
+        return Err(eyre::eyre!("Pass at least one --host to include in the certificate"));
+    }
+    if !force {
+        for path in [certificate_path, key_path] {
+            if fs::metadata(path).await.is_ok() {
+                return Err(eyre::eyre!("{} already exists. Pass --force to overwrite it", path.display()));
+            }
+        }
+    }
+    let certificate = rcgen::generate_simple_self_signed(hosts)?;
+    fs::write(certificate_path, certificate.serialize_pem()?).await?;
+    fs::write(key_path, certificate.serialize_private_key_pem()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    // Not set, so the files are read from disk
+    const UNSET_ENV_VAR: &str = "THEBESTOFCMU_TEST_UNSET";
+
+    #[async_std::test]
+    async fn generated_certificate_loads() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let certificate_path = directory.path().join("server-certificate.pem");
+        let key_path = directory.path().join("server-certificate.key");
+        generate(vec![String::from("localhost")], &certificate_path, &key_path, false).await?;
+
+        let certificates = crate::load_certificates(&ConfigFile::new(certificate_path, UNSET_ENV_VAR)).await?;
+        assert_eq!(1, certificates.len());
+        crate::load_private_key(&ConfigFile::new(key_path, UNSET_ENV_VAR)).await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn refuse_to_overwrite() -> Result<()> {
+        let directory = tempfile::tempdir()?;
+        let certificate_path = directory.path().join("server-certificate.pem");
+        let key_path = directory.path().join("server-certificate.key");
+        std::fs::write(&key_path, "existing key")?;
+        let hosts = || vec![String::from("localhost")];
+
+        let error = generate(hosts(), &certificate_path, &key_path, false).await.unwrap_err();
+        assert!(error.to_string().contains("--force"), "{}", error);
+        assert_eq!("existing key", std::fs::read_to_string(&key_path)?);
+
+        generate(hosts(), &certificate_path, &key_path, true).await?;
+        assert_ne!("existing key", std::fs::read_to_string(&key_path)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ * thebestofcmu
+ * Copyright © 2022 Anand Beh
+ *
+ * thebestofcmu is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * thebestofcmu is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with thebestofcmu. If not, see <https://www.gnu.org/licenses/>
+ * and navigate to version 3 of the GNU Affero General Public License.
+ */
+
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use eyre::Result;
+#[cfg(not(unix))]
+use async_ctrlc::CtrlC;
+#[cfg(unix)]
+use async_std::task;
+#[cfg(unix)]
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+/// A request from outside the process to stop the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// Ctrl-C, or SIGINT
+    Interrupt,
+    /// SIGTERM, as sent by systemd and Docker
+    Terminate
+}
+
+impl Display for ShutdownSignal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Interrupt if cfg!(unix) => "SIGINT",
+            Self::Interrupt => "Ctrl-C",
+            Self::Terminate => "SIGTERM"
+        })
+    }
+}
+
+/// Listens for the signals which stop the server. Listening starts as soon as this
+/// is registered, so a signal sent while the server starts is not missed.
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    signals: Signals,
+    #[cfg(not(unix))]
+    ctrl_c: CtrlC
+}
+
+impl ShutdownSignals {
+    #[cfg(unix)]
+    pub fn register() -> Result<Self> {
+        Ok(Self { signals: Signals::new([SIGINT, SIGTERM])? })
+    }
+
+    #[cfg(not(unix))]
+    pub fn register() -> Result<Self> {
+        Ok(Self { ctrl_c: CtrlC::new()? })
+    }
+
+    /// Waits for the first signal
+    #[cfg(unix)]
+    pub async fn recv(mut self) -> ShutdownSignal {
+        task::spawn_blocking(move || match self.signals.forever().next() {
+            Some(SIGTERM) => ShutdownSignal::Terminate,
+            _ => ShutdownSignal::Interrupt
+        }).await
+    }
+
+    /// Waits for the first signal
+    #[cfg(not(unix))]
+    pub async fn recv(self) -> ShutdownSignal {
+        self.ctrl_c.await;
+        ShutdownSignal::Interrupt
+    }
+}
+
+/// Resolves once the signal does, logging which signal it was
+pub async fn shutdown_on<F>(signal: F)
+    where F: Future<Output=ShutdownSignal> {
+
+    let signal = signal.await;
+    log::info!("Received {}. Shutting down....", signal);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::future;
+    use crate::captured_log;
+
+    #[async_std::test]
+    async fn log_signal() {
+        captured_log::install();
+        shutdown_on(future::ready(ShutdownSignal::Terminate)).await;
+        let records = captured_log::records(|record| record.target.ends_with("::shutdown"));
+        assert_eq!(vec!["Received SIGTERM. Shutting down...."],
+                   records.iter().map(|record| record.message.as_str()).collect::<Vec<_>>());
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn receive_sigterm() -> Result<()> {
+        let signals = ShutdownSignals::register()?;
+        signal_hook::low_level::raise(SIGTERM)?;
+        assert_eq!(ShutdownSignal::Terminate, signals.recv().await);
+        Ok(())
+    }
+}
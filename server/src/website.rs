@@ -17,87 +17,376 @@
  * and navigate to version 3 of the GNU Affero General Public License.
  */
 
-use hyper::{Body, Uri};
-use hyper::http::uri;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use eyre::Result;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use hyper::Body;
+use hyper::body::Bytes;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tinytemplate::TinyTemplate;
 use thebestofcmu_common::PostPath;
+use crate::config::EventDetails;
+use crate::event::Event;
 
 pub struct Website {
-    pub favicon: &'static [u8],
-    pub kayaking_image: &'static [u8]
+    pub favicon: Asset,
+    pub kayaking_image: Asset,
+    /// The page of the default event
+    pub main_page: String,
+    /// The page of the default event, compressed once for clients accepting gzip
+    pub main_page_gzip: Bytes,
+    pub minify_html: bool,
+    /// The default event's details, which also stand in for those of events without their own
+    pub event_details: EventDetails,
+    /// Trusted HTML appended to every page
+    pub footer_html: String
 }
 
-fn request_path(request_uri: &uri::Parts) -> &str {
-    (&request_uri.path_and_query)
-        .as_ref()
-        .map(|path| path.path())
-        .unwrap_or("/")
+/// A file embedded in the binary, and the entity tag of its contents
+pub struct Asset {
+    pub bytes: &'static [u8],
+    pub etag: String
 }
 
+impl Asset {
+    pub fn new(bytes: &'static [u8]) -> Self {
+        Self { bytes, etag: etag_of(bytes) }
+    }
+}
+
+pub const DEFAULT_FOOTER: &str = "Source code available upon written request.";
+
+pub const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// The whole of the not-found response for clients which do not take HTML
+pub const NOT_FOUND_MESSAGE: &str = "According to my book-keeping, that page does not exist.";
+
+// In TinyTemplate syntax, which needs literal opening braces escaped as \{
+const MAIN_PAGE_TEMPLATE: &str = include_str!("main-page.html");
+
+const DEFAULT_TITLE: &str = "Welcome, to the First Day of Class";
+
 impl Website {
-    pub fn validate_post_path(&self, request_uri: Uri) -> Option<PostPath> {
-        let request_uri = request_uri.into_parts();
-        let request_path = request_path(&request_uri);
+    /// Takes the path within the event, without any /e/{slug} prefix
+    pub fn validate_post_path(&self, request_path: &str) -> Option<PostPath> {
         PostPath::from_str(if request_path.starts_with('/') { &request_path[1..] } else { request_path })
     }
 
-    pub async fn yield_site_body(&self, request_uri: Uri) -> Option<Body> {
-        let request_uri = request_uri.into_parts();
-        let request_path = request_path(&request_uri);
+    /// Takes the path within the event, without any /e/{slug} prefix
+    pub async fn yield_site_body(&self, request_path: &str) -> Option<Body> {
         Some(match request_path {
-            "/" => Body::from(main_page_content()),
-            "/favicon.ico" => Body::from(self.favicon),
-            "/kayaking-background.webp" => Body::from(self.kayaking_image),
+            "/" => Body::from(self.main_page.clone()),
+            "/favicon.ico" => Body::from(self.favicon.bytes),
+            "/kayaking-background.webp" => Body::from(self.kayaking_image.bytes),
             _ => return None
         })
     }
 
-}
+    /// The embedded file at the path within the event, if any
+    pub fn asset(&self, request_path: &str) -> Option<&Asset> {
+        Some(match request_path {
+            "/favicon.ico" => &self.favicon,
+            "/kayaking-background.webp" => &self.kayaking_image,
+            _ => return None
+        })
+    }
+
+    /// The MIME type of the page or asset at the path within the event
+    pub fn content_type(&self, request_path: &str) -> Option<&'static str> {
+        Some(match request_path {
+            "/" => HTML_CONTENT_TYPE,
+            "/favicon.ico" => "image/x-icon",
+            "/kayaking-background.webp" => "image/webp",
+            _ => return None
+        })
+    }
+
+    /// The main page showing the event's own title and details
+    pub fn event_page(&self, event: &Event) -> Result<String> {
+        render_page(self.minify_html, &self.footer_html,
+                    event.title_html.as_deref().unwrap_or(DEFAULT_TITLE),
+                    &self.event_details,
+                    event.details_html.as_deref())
+    }
 
-fn main_page_content() -> &'static str {
-    r#"
-<!DOCTYPE html>
-<head></head>
-<body>
-<h1 style="color: #5e9ca0; text-align: center;">Welcome, to the First Day of Class</h1>
-<p style="text-align: center;">You are hereby invited to come kayaking on the pristine waters of River Allegheny. The river, located far off to the north, beyond city limits, is a faraway place of wonder where a CMU student is a rare sight to behold. In a valley rimmed with vibrant treetops, exotic birds fly to and fro while fish dance in the water. Unlike the tumult of academic life, all elements of this valley cohere and are at harmony with one another. The river waters the plants, whose roots in turn hold the earthwork, preventing erosion; while the tree leaves provide shadow to the water and shelter to all that lives within.</p>
-<p style="text-align: center;">Yet there can be no serenity without danger, for the river is swift and merciless. From the depths of the current swell monstrous rocks and boulders, creating a continuous challenge of navigation for the few voyagers who chance this way. Those fortunate enough to survive, tell tall tales of adventure.</p>
-<p style="text-align: center;">This website is for fun: entirely theatrical. The location, exaggerated. All the same, kayaking is an enjoyable activity, whether you prefer strenous exertion or relaxing vacation. This school year, surely, will be a spectacular one.</p>
-<ul>
-<li style="text-align: left;"><strong>Date:</strong> 3 September 2022</li>
-<li style="text-align: left;"><strong>Time and Place:</strong> Meet at&nbsp;12:15 PM, <em><strong>sharp,</strong></em> at Fifth &amp; Craig intersection (St. Paul's Cathedral)</li>
-<li style="text-align: left;"><strong>Cost:</strong> $40, cash only</li>
-</ul>
-<p style="text-align: left;">To RSVP, please reply by SMS to the coordinator who linked you to this website. If you want to invite anyone else, please ask the coordinator.</p>
-<p style="text-align: center;">&nbsp;</p>
-<p><img style="display: block; margin-left: auto; margin-right: auto;" src="./kayaking-background.webp" alt="kayaking-image" width="1200" height="795" /></p>
-<div id="spinner" style="position: relative;">
-  <div class="spinner">Loading...</div>
-</div>
-<script type="module">
-  import init from './pkg/thebestofcmu-client.js';
-  init().finally(() => {
-    document.getElementById("spinner").remove();
-  });
-</script>
-<p style="text-align: right;">Source code available upon written request.</p>
+    /// Styled like the main page, with a way back to it
+    pub fn not_found_page(&self) -> String {
+        format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Not found</title>
+</head>
+<body style="font-family: sans-serif; margin: 2em;">
+<h1 style="color: #5e9ca0; text-align: center;">Lost on the River</h1>
+<p style="text-align: center;">{}</p>
+<p style="text-align: center;">Perhaps the current carried you astray. <a href="/" style="color: #5e9ca0;">Paddle back to the invitation</a>.</p>
+<p style="text-align: right;">{}</p>
 </body>
 </html>
-    "#
+"#, NOT_FOUND_MESSAGE, self.footer_html)
+    }
+
+}
+
+/// A strong entity tag identifying a page
+pub fn page_etag(page: &str) -> String {
+    etag_of(page)
+}
+
+fn etag_of<T: Hash + ?Sized>(content: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Compresses a page for clients which accept gzip
+pub fn gzip(page: &str) -> io::Result<Bytes> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(page.as_bytes())?;
+    Ok(Bytes::from(encoder.finish()?))
+}
+
+/// A Content-Security-Policy for the page, allowing its inline scripts by their hashes.
+/// The WASM client is compiled from the page's own origin.
+pub fn content_security_policy(page: &str) -> String {
+    let mut script_src = String::from("'self' 'wasm-unsafe-eval'");
+    for script in inline_scripts(page) {
+        script_src.push_str(&format!(" 'sha256-{}'", base64::encode(Sha256::digest(script.as_bytes()))));
+    }
+    format!("default-src 'self'; script-src {}; style-src 'self' 'unsafe-inline'; \
+             img-src 'self'; object-src 'none'; base-uri 'none'; frame-ancestors 'none'", script_src)
+}
+
+// The contents of each script element without a src attribute
+fn inline_scripts(page: &str) -> Vec<&str> {
+    let mut scripts = Vec::new();
+    let mut remaining = page;
+    while let Some(start) = remaining.find("<script") {
+        remaining = &remaining[start..];
+        let (tag_end, end) = match (remaining.find('>'), remaining.find("</script>")) {
+            (Some(tag_end), Some(end)) if tag_end < end => (tag_end, end),
+            _ => break
+        };
+        if !remaining[..tag_end].contains(" src=") {
+            scripts.push(&remaining[tag_end + 1..end]);
+        }
+        remaining = &remaining[end..];
+    }
+    scripts
+}
+
+/// Yields the main page, collapsing its whitespace unless the pretty version is wanted
+pub fn main_page(minify: bool, footer_html: &str, details: &EventDetails) -> Result<String> {
+    render_page(minify, footer_html, DEFAULT_TITLE, details, None)
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            other => escaped.push(other)
+        }
+    }
+    escaped
+}
+
+// Values are escaped unless marked unescaped in the template. Those are trusted HTML.
+#[derive(Serialize)]
+struct PageContext<'p> {
+    title_html: &'p str,
+    details: &'p EventDetails,
+    /// Replaces the list of details
+    details_html: Option<&'p str>,
+    footer_html: &'p str
+}
+
+fn render_page(minify: bool,
+               footer_html: &str,
+               title_html: &str,
+               details: &EventDetails,
+               details_html: Option<&str>) -> Result<String> {
+    let mut template = TinyTemplate::new();
+    template.add_template("main-page", MAIN_PAGE_TEMPLATE)?;
+    let content = template.render("main-page", &PageContext { title_html, details, details_html, footer_html })?;
+    Ok(if minify {
+        minify_html(&content)
+    } else {
+        content
+    })
+}
+
+fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut in_script = false;
+    for line in html.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(previous) = minified.chars().next_back() {
+            if in_script {
+                // Line breaks may be significant to JavaScript
+                minified.push('\n');
+            } else if previous != '>' || !line.starts_with('<') {
+                minified.push(' ');
+            }
+        }
+        minified.push_str(line);
+        if let Some(script_start) = line.rfind("<script") {
+            in_script = !line[script_start..].contains("</script>");
+        } else if line.contains("</script>") {
+            in_script = false;
+        }
+    }
+    minified
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use eyre::Result;
-    use hyper::http::uri::PathAndQuery;
+    use crate::event::DEFAULT_EVENT_ID;
+
+    fn website(main_page: String, footer_html: &str) -> Website {
+        Website {
+            favicon: Asset::new(&[]),
+            kayaking_image: Asset::new(&[]),
+            main_page,
+            main_page_gzip: Bytes::new(),
+            minify_html: true,
+            event_details: EventDetails::default(),
+            footer_html: String::from(footer_html)
+        }
+    }
+
+    #[test]
+    fn policy_allows_inline_scripts() -> Result<()> {
+        let page = r#"<p>Hi</p><script type="module"></script><script src="./pkg/client.js"></script>"#;
+        let policy = content_security_policy(page);
+        // The hash of no script at all
+        assert!(policy.contains("script-src 'self' 'wasm-unsafe-eval' 'sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=';"),
+                "{}", policy);
+
+        let page = main_page(true, DEFAULT_FOOTER, &EventDetails::default())?;
+        let scripts = inline_scripts(&page);
+        assert_eq!(1, scripts.len());
+        assert!(scripts[0].contains("import init from './pkg/thebestofcmu-client.js';"));
+        assert_eq!(1, content_security_policy(&page).matches("'sha256-").count());
+        Ok(())
+    }
+
+    #[test]
+    fn post_path() {
+        let website = website(String::new(), "");
+        assert_eq!(Some(PostPath::EnterRsvp), website.validate_post_path("/enter-rsvp"));
+        assert_eq!(None, website.validate_post_path("/e/fall-trip/enter-rsvp"));
+    }
+
+    #[test]
+    fn minified_main_page() -> Result<()> {
+        let pretty = main_page(false, DEFAULT_FOOTER, &EventDetails::default())?;
+        let minified = main_page(true, DEFAULT_FOOTER, &EventDetails::default())?;
+        assert!(minified.len() < pretty.len());
+        assert!(minified.starts_with("<!DOCTYPE html><head></head><body>"));
+        assert!(minified.contains(r#"<div id="spinner" style="position: relative;"><div class="spinner">"#));
+        assert!(minified.contains(r#"<script type="module">
+import init from './pkg/thebestofcmu-client.js';
+init().catch(() => {
+document.getElementById("client-unavailable").style.display = "block";
+}).finally(() => {
+document.getElementById("spinner").remove();
+});
+</script>"#));
+        Ok(())
+    }
+
+    #[test]
+    fn fallback_when_client_unavailable() -> Result<()> {
+        let page = main_page(true, DEFAULT_FOOTER, &EventDetails::default())?;
+        assert!(page.contains(r#"<p id="client-unavailable" style="display: none;"#));
+        assert!(page.contains(r#"document.getElementById("client-unavailable").style.display = "block";"#));
+        assert!(page.contains("<noscript>"));
+        Ok(())
+    }
+
+    #[test]
+    fn footer_on_every_page() -> Result<()> {
+        let footer_html = r#"Questions? <a href="mailto:kayaking@example.com">Email us</a>"#;
+        let website = website(main_page(true, footer_html, &EventDetails::default())?, footer_html);
+        assert!(website.main_page.contains(footer_html));
+        assert!(website.not_found_page().contains(footer_html));
+        assert!(!website.main_page.contains(DEFAULT_FOOTER));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn content_type_of_each_path() {
+        let website = website(String::new(), "");
+        assert_eq!(Some("text/html; charset=utf-8"), website.content_type("/"));
+        assert_eq!(Some("image/x-icon"), website.content_type("/favicon.ico"));
+        assert_eq!(Some("image/webp"), website.content_type("/kayaking-background.webp"));
+        assert_eq!(None, website.content_type("/missing.png"));
+        // Anything with a body has a type
+        for path in ["/", "/favicon.ico", "/kayaking-background.webp", "/missing.png"] {
+            assert_eq!(website.yield_site_body(path).await.is_some(), website.content_type(path).is_some(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn page_per_event() -> Result<()> {
+        let website = website(main_page(true, DEFAULT_FOOTER, &EventDetails::default())?, DEFAULT_FOOTER);
+        let mut event = Event {
+            id: DEFAULT_EVENT_ID + 1,
+            slug: String::from("fall-trip"),
+            title_html: None,
+            details_html: None
+        };
+        assert_eq!(website.main_page, website.event_page(&event)?);
+
+        event.title_html = Some(String::from("Fall Foliage Paddle"));
+        event.details_html = Some(String::from("<p><strong>Date:</strong> 15 October 2022</p>"));
+        let page = website.event_page(&event)?;
+        assert!(page.contains(">Fall Foliage Paddle</h1>"), "{}", page);
+        assert!(page.contains("<p><strong>Date:</strong> 15 October 2022</p>"));
+        assert!(!page.contains("3 September 2022"));
+        assert!(page.contains(DEFAULT_FOOTER));
+        assert_ne!(page_etag(&website.main_page), page_etag(&page));
+        Ok(())
+    }
+
+    #[test]
+    fn configured_details() -> Result<()> {
+        let details = EventDetails {
+            date: String::from("15 October 2022"),
+            meeting_time: String::from("9:00 AM"),
+            location: String::from("the Millvale boat launch"),
+            cost: String::from("Free & easy")
+        };
+        let page = main_page(true, DEFAULT_FOOTER, &details)?;
+        assert!(page.contains("<strong>Date:</strong> 15 October 2022</li>"), "{}", page);
+        assert!(page.contains("Meet at&nbsp;9:00 AM at the Millvale boat launch</li>"), "{}", page);
+        assert!(page.contains("<strong>Cost:</strong> Free &amp; easy</li>"), "{}", page);
+        assert!(!page.contains("3 September 2022"));
+        Ok(())
+    }
 
     #[test]
-    fn post_path() -> Result<()> {
-        let website = Website { favicon: &[], kayaking_image: &[] };
-        let uri = Uri::builder()
-            .path_and_query(PathAndQuery::from_static("/enter-rsvp"))
-            .build()?;
-        assert_eq!(Some(PostPath::EnterRsvp), website.validate_post_path(uri));
+    fn details_escaped() -> Result<()> {
+        let details = EventDetails {
+            location: String::from("<script>alert('ahoy')</script>"),
+            ..Default::default()
+        };
+        let page = main_page(true, DEFAULT_FOOTER, &details)?;
+        assert!(page.contains("&lt;script&gt;alert(&#39;ahoy&#39;)&lt;/script&gt;"), "{}", page);
+        assert!(!page.contains("<script>alert"));
+        assert!(page.contains("<strong>Date:</strong> 3 September 2022</li>"));
         Ok(())
     }
 }